@@ -0,0 +1,149 @@
+use std::{thread, time::Instant};
+
+use crate::{
+    board::{BoardState, Faction, from_readable_move, to_linind},
+    bot::{SearchOptions, Searcher, describe_score, format_pv},
+};
+
+// Each bench position is timed as the fastest of this many searches, since a
+// single run varies by a third from one run to the next
+const BENCH_RUNS: usize = 3;
+
+// Fixed positions given as move sequences from the standard setup
+const POSITIONS: [&str; 4] = [
+    "",
+    "D1-D3 F4-C4",
+    "D1-D3 F4-C4 K5-I5 H6-H9 E11-E9 G5-G2",
+    "D1-D3 F4-C4 K5-I5 H6-H9 E11-E9 G5-G2 A7-C7 E7-E8 B6-B8 D6-D8",
+];
+
+// Plays out a sequence of moves like "D1-D3 F4-C4" from the standard setup,
+// returning the resulting position and the faction to move.
+pub fn play_line(line: &str) -> Option<(BoardState, Faction)> {
+    let mut board = BoardState::standard_setup();
+    let mut turn = Faction::Black;
+
+    for mv in line.split_whitespace() {
+        let [from, to] = from_readable_move(mv)?;
+
+        if Faction::try_from(board.get_2d(from)?).ok() != Some(turn)
+            || !board.moves_from(from)[to_linind(to)?]
+        {
+            return None;
+        }
+
+        board.do_move(from, to);
+        turn = turn.other_faction();
+    }
+
+    Some((board, turn))
+}
+
+// Time to depth and nodes per second for 1, 2, 4, ... threads, up to
+// `max_threads`, over all bench positions
+pub fn smp_scaling(depth: u32, max_threads: usize, options: SearchOptions) {
+    let mut single_thread_time = None;
+    let mut single_thread_nodes = None;
+
+    // Only the speedups in time to depth are measured, and only up to the
+    // cores this machine has
+    println!(
+        "{} cores available",
+        thread::available_parallelism().map_or(1, |n| n.get())
+    );
+
+    for threads in (0..)
+        .map(|i| 1 << i)
+        .take_while(|&n| n < max_threads)
+        .chain([max_threads])
+    {
+        let mut nodes = 0;
+        let mut main_nodes = 0;
+        let mut time = 0.0;
+
+        for line in POSITIONS {
+            let (board, turn) = play_line(line).expect("Invalid position");
+
+            let mut searcher =
+                Searcher::new(SearchOptions { threads, ..options });
+
+            let t = Instant::now();
+            searcher.search(board, turn, depth, |_| {});
+            time += t.elapsed().as_secs_f64();
+
+            nodes += searcher.nodes;
+            main_nodes += searcher.nodes - searcher.helper_nodes;
+        }
+
+        let single_thread_time = *single_thread_time.get_or_insert(time);
+        let single_thread_nodes = *single_thread_nodes.get_or_insert(nodes);
+
+        // Sharing one core, every thread runs at about 1/n of its speed, so
+        // the main thread gets as far as it would with n cores. How many
+        // nodes it needs to reach the depth then estimates the speedup the
+        // threads would have on n cores. It is an upper bound, since threads
+        // on separate cores also compete for memory and the shared table.
+        println!(
+            "threads: {threads:>3}, time to depth {depth}: {time:.2}s \
+            (measured speedup {:.2}), nodes: {nodes}, {:.0} knps, main \
+            thread nodes: {main_nodes} (estimated upper bound on the \
+            speedup on {threads} cores {:.2})",
+            single_thread_time / time,
+            nodes as f64 / time / 1000.0,
+            single_thread_nodes as f64 / main_nodes as f64,
+        );
+    }
+}
+
+pub fn run(depth: u32, options: SearchOptions) {
+    let mut total_nodes = 0;
+    let mut total_time = 0.0;
+
+    for line in POSITIONS {
+        let (board, turn) = play_line(line).expect("Invalid bench position");
+
+        // Fresh searches of the same position visit the same nodes, so only
+        // the time differs between runs. `search` never plays from the book,
+        // so every position is searched.
+        let (t, nodes, result) = (0..BENCH_RUNS)
+            .map(|_| {
+                let mut searcher = Searcher::new(options);
+
+                let t = Instant::now();
+                let result = searcher.search(board, turn, depth, |_| {});
+
+                (t.elapsed(), searcher.nodes, result)
+            })
+            .min_by_key(|&(t, ..)| t)
+            .unwrap();
+
+        total_nodes += nodes;
+        total_time += t.as_secs_f64();
+
+        println!(
+            "{:>2} moves: {} ({}), nodes: {}, took: {t:.2?}, {:.0} knps",
+            line.split_whitespace().count(),
+            format_pv(&result.pv),
+            describe_score(result.score),
+            nodes,
+            nodes as f64 / t.as_secs_f64() / 1000.0,
+        );
+    }
+
+    println!(
+        "Total nodes: {total_nodes}, took: {total_time:.2}s, {:.0} knps",
+        total_nodes as f64 / total_time / 1000.0,
+    );
+}
+
+// Prints an `info` line for every iteration of a search of the position after
+// `line`.
+pub fn analyse(line: &str, depth: u32, options: SearchOptions) {
+    let (board, turn) = play_line(line).expect("Invalid position");
+
+    let mut searcher = Searcher::new(options);
+
+    searcher.search(board, turn, depth, |result| {
+        println!("{}", result.info_line(turn));
+    });
+}
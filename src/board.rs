@@ -1,7 +1,7 @@
-use std::fmt::Display;
+use std::{fmt::Display, mem};
 
 use bitarray::BitArray;
-use crossterm::style::Stylize;
+use crossterm::style::{Color, Stylize};
 
 pub const W: usize = 11;
 
@@ -11,10 +11,32 @@ pub const M: usize = (W * W).div_ceil(usize::BITS as usize);
 pub const TOWERS: BitArray<M> =
     BitArray([1152921504606848001, 72127962782105600]);
 
+// The towers without the throne
+pub const CORNERS: BitArray<M> = BitArray([1025, 72127962782105600]);
+
 pub fn to_readable_coord([y, x]: [u16; 2]) -> String {
     format!("{}{}", b"ABCDEFGHIJK"[x as usize] as char, y + 1)
 }
 
+pub fn from_readable_coord(s: &str) -> Option<[u16; 2]> {
+    let mut chars = s.chars();
+
+    let x = "ABCDEFGHIJK".find(chars.next()?.to_ascii_uppercase())? as u16;
+    let y = chars.as_str().parse::<u16>().ok()?.checked_sub(1)?;
+
+    (y < W as u16).then_some([y, x])
+}
+
+pub fn to_readable_move([from, to]: [[u16; 2]; 2]) -> String {
+    format!("{}-{}", to_readable_coord(from), to_readable_coord(to))
+}
+
+pub fn from_readable_move(s: &str) -> Option<[[u16; 2]; 2]> {
+    let (from, to) = s.split_once('-')?;
+
+    Some([from_readable_coord(from)?, from_readable_coord(to)?])
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Piece {
     Empty = 0,
@@ -65,7 +87,7 @@ impl From<Piece> for [bool; 2] {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Faction {
     Black = 0,
     White,
@@ -97,11 +119,29 @@ pub struct BoardState(pub [BitArray<M>; 2]);
 
 impl Display for BoardState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.render(f, BitArray::new())
+        self.render(f, |_| None)
     }
 }
 
-fn _to_2d(i: usize) -> [u16; 2] {
+const LEFT_COLUMN: u128 = 0x00004008010020040080100200400801;
+
+const RIGHT_COLUMN: u128 = LEFT_COLUMN << (W - 1);
+
+const FULL_BOARD: u128 = (1 << (W * W)) - 1;
+
+// Squares orthogonally adjacent to any of the given squares
+pub fn neighbours(squares: BitArray<M>) -> BitArray<M> {
+    let squares: u128 = unsafe { mem::transmute(squares) };
+
+    let n = (squares << W)
+        | (squares >> W)
+        | ((squares & !RIGHT_COLUMN) << 1)
+        | ((squares & !LEFT_COLUMN) >> 1);
+
+    unsafe { mem::transmute(n & FULL_BOARD) }
+}
+
+fn to_2d(i: usize) -> [u16; 2] {
     let y = (i / W) as u16;
     let x = (i % W) as u16;
 
@@ -116,6 +156,32 @@ pub fn to_linind([y, x]: [u16; 2]) -> Option<usize> {
     Some(x as usize + y as usize * W)
 }
 
+// The board looks the same under 4 rotations, each optionally mirrored
+pub const SYMMETRIES: usize = 8;
+
+// Maps a square through one of the symmetries: bit 2 swaps rows and columns,
+// then bit 0 flips rows and bit 1 flips columns
+pub fn transform_square([y, x]: [u16; 2], symmetry: usize) -> [u16; 2] {
+    let n = W as u16 - 1;
+
+    let [y, x] = if symmetry & 4 != 0 { [x, y] } else { [y, x] };
+
+    [
+        if symmetry & 1 != 0 { n - y } else { y },
+        if symmetry & 2 != 0 { n - x } else { x },
+    ]
+}
+
+// The symmetry that undoes `symmetry`. After a swap, the row flip acts on
+// the columns of the original board and the other way around.
+pub fn inverse_symmetry(symmetry: usize) -> usize {
+    if symmetry & 4 != 0 {
+        4 | (symmetry & 1) << 1 | (symmetry & 2) >> 1
+    } else {
+        symmetry
+    }
+}
+
 impl BoardState {
     pub fn new() -> Self {
         Self([BitArray::new(); 2])
@@ -197,7 +263,7 @@ impl BoardState {
         self.set(to_linind([y, x]).unwrap(), val)
     }
 
-    pub fn _empties(self) -> BitArray<M> {
+    pub fn empties(self) -> BitArray<M> {
         !self.0[0] & !self.0[1]
     }
 
@@ -209,6 +275,50 @@ impl BoardState {
         !self.0[0] & self.0[1]
     }
 
+    pub fn king(self) -> BitArray<M> {
+        self.0[0] & !self.0[1]
+    }
+
+    pub fn king_coord(self) -> Option<[u16; 2]> {
+        let i = self.king().trues_iter().next()?;
+
+        Some([(i / W) as u16, (i % W) as u16])
+    }
+
+    // Corners the king can move to right away
+    pub fn king_escapes(self) -> BitArray<M> {
+        match self.king_coord() {
+            Some(coord) => self.moves_from(coord) & CORNERS,
+            None => BitArray::new(),
+        }
+    }
+
+    pub fn transformed(self, symmetry: usize) -> Self {
+        let mut planes = [BitArray::new(); 2];
+
+        for (new, old) in planes.iter_mut().zip(self.0) {
+            for i in old.trues_iter() {
+                let square = transform_square(to_2d(i), symmetry);
+
+                new.set(to_linind(square).unwrap(), true);
+            }
+        }
+
+        Self(planes)
+    }
+
+    // The same position for every orientation of the board, and the symmetry
+    // that turns this board into it
+    pub fn canonical(self) -> (Self, usize) {
+        let key =
+            |board: &Self| -> [u128; 2] { unsafe { mem::transmute(board.0) } };
+
+        (0..SYMMETRIES)
+            .map(|symmetry| (self.transformed(symmetry), symmetry))
+            .min_by_key(|(board, _)| key(board))
+            .unwrap()
+    }
+
     pub fn select_faction(self, turn: Faction) -> BitArray<M> {
         match turn {
             Faction::Black => self.blacks(),
@@ -219,7 +329,7 @@ impl BoardState {
     fn render(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        highlights: BitArray<M>,
+        colour: impl Fn([u16; 2]) -> Option<Color>,
     ) -> std::fmt::Result {
         writeln!(f, "┏━━━┓ ┏━━━┳━━━┳━━━┳━━━┳━━━┳━━━┳━━━┳━━━┳━━━┳━━━┳━━━┓")?;
         writeln!(f, "┃ X ┃ ┃ A ┃ B ┃ C ┃ D ┃ E ┃ F ┃ G ┃ H ┃ I ┃ J ┃ K ┃")?;
@@ -251,16 +361,6 @@ impl BoardState {
                     _ => write!(f, "│")?,
                 };
 
-                let highlight = highlights
-                    .get(to_linind([row as u16, col as u16]).unwrap())
-                    .unwrap();
-
-                if highlight {
-                    write!(f, "{}", "▐".dark_grey())?;
-                } else {
-                    write!(f, " ")?;
-                }
-
                 let piece = match x {
                     Piece::Empty => " ",
                     Piece::King => "ᛝ",
@@ -269,16 +369,15 @@ impl BoardState {
                 }
                 .bold();
 
-                if highlight {
-                    write!(f, "{}", piece.on_dark_grey())?;
-                } else {
-                    write!(f, "{}", piece)?;
-                }
-
-                if highlight {
-                    write!(f, "{}", "▌".dark_grey())?;
-                } else {
-                    write!(f, " ")?;
+                match colour([row as u16, col as u16]) {
+                    Some(colour) => write!(
+                        f,
+                        "{}{}{}",
+                        "▐".with(colour),
+                        piece.on(colour),
+                        "▌".with(colour)
+                    )?,
+                    None => write!(f, " {piece} ")?,
                 }
             }
 
@@ -390,10 +489,89 @@ impl BoardState {
     }
 }
 
-pub struct HighlightedBoardState(pub BoardState, pub BitArray<M>);
+// A board with squares highlighted: the legal moves or principal variation,
+// the last move, the pieces it just captured and the keyboard cursor
+pub struct HighlightedBoardState {
+    pub board: BoardState,
+    pub highlights: BitArray<M>,
+    pub last_move: Option<[[u16; 2]; 2]>,
+    pub captured: BitArray<M>,
+    pub cursor: Option<[u16; 2]>,
+}
+
+impl HighlightedBoardState {
+    pub fn new(board: BoardState, highlights: BitArray<M>) -> Self {
+        Self {
+            board,
+            highlights,
+            last_move: None,
+            captured: BitArray::new(),
+            cursor: None,
+        }
+    }
+}
 
 impl Display for HighlightedBoardState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.render(f, self.1)
+        self.board.render(f, |square| {
+            let i = to_linind(square).unwrap();
+
+            if self.cursor == Some(square) {
+                Some(Color::Yellow)
+            } else if self.captured[i] {
+                Some(Color::DarkRed)
+            } else if self.highlights[i] {
+                Some(Color::DarkGrey)
+            } else if self.last_move.is_some_and(|mv| mv.contains(&square)) {
+                Some(Color::DarkBlue)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_symmetry_undoes_transform() {
+        for symmetry in 0..SYMMETRIES {
+            let inverse = inverse_symmetry(symmetry);
+
+            for i in 0..W * W {
+                let square = to_2d(i);
+                let there = transform_square(square, symmetry);
+
+                assert_eq!(transform_square(there, inverse), square);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_is_the_same_for_every_symmetry() {
+        // No symmetry maps this position onto itself
+        let mut board = BoardState::standard_setup();
+        board.do_move([0, 3], [2, 3]);
+        board.do_move([3, 5], [3, 8]);
+
+        let (canonical, _) = board.canonical();
+
+        for symmetry in 0..SYMMETRIES {
+            let transformed = board.transformed(symmetry);
+
+            assert_eq!(transformed == board, symmetry == 0);
+            assert_eq!(transformed.canonical().0, canonical);
+        }
+    }
+
+    #[test]
+    fn standard_setup_is_symmetric() {
+        let board = BoardState::standard_setup();
+
+        for symmetry in 0..SYMMETRIES {
+            assert_eq!(board.transformed(symmetry), board);
+        }
     }
 }
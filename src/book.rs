@@ -0,0 +1,286 @@
+use std::{cmp::Reverse, fmt::Display, fs, mem, sync::OnceLock};
+
+use ahash::RandomState;
+use bitarray::BitArray;
+use hashbrown::HashMap;
+
+use crate::{
+    board::{
+        BoardState, Faction, M, from_readable_move, inverse_symmetry,
+        to_readable_move, transform_square,
+    },
+    bot::Move,
+    rng::Rng,
+    selfplay::{GameRecord, Outcome},
+};
+
+// Book loaded at startup with `--book`
+static STARTUP_BOOK: OnceLock<Book> = OnceLock::new();
+
+// How a move played from a book position went, for the faction playing it
+#[derive(Debug, Clone, Copy)]
+pub struct BookMove {
+    pub mv: Move,
+    pub games: u32,
+    pub wins: u32,
+    pub draws: u32,
+}
+
+impl BookMove {
+    // Twice the points the move scored, which is how often it gets picked
+    fn weight(&self) -> u32 {
+        2 * self.wins + self.draws
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        self.weight() as f64 / (2 * self.games) as f64
+    }
+}
+
+// Moves played from positions early in past games. Positions are stored in
+// canonical form, with their moves turned to match, so one entry covers every
+// orientation of a position.
+#[derive(Default)]
+pub struct Book {
+    positions: HashMap<(BoardState, Faction), Vec<BookMove>, RandomState>,
+}
+
+fn faction_name(faction: Faction) -> &'static str {
+    match faction {
+        Faction::Black => "black",
+        Faction::White => "white",
+    }
+}
+
+fn turn_move([from, to]: Move, symmetry: usize) -> Move {
+    [
+        transform_square(from, symmetry),
+        transform_square(to, symmetry),
+    ]
+}
+
+impl Book {
+    // Counts the moves of the first `plies` plies of every game, keeping
+    // those played in at least `min_games` games
+    pub fn build(records: &[GameRecord], plies: usize, min_games: u32) -> Self {
+        let mut book = Self::default();
+
+        for record in records {
+            if record.outcome == Outcome::Unfinished {
+                continue;
+            }
+
+            for ((board, turn), &mv) in
+                record.positions().zip(&record.moves).take(plies)
+            {
+                let (board, symmetry) = board.canonical();
+                let mv = turn_move(mv, symmetry);
+
+                let moves = book.positions.entry((board, turn)).or_default();
+
+                let i = match moves.iter().position(|x| x.mv == mv) {
+                    Some(i) => i,
+                    None => {
+                        moves.push(BookMove {
+                            mv,
+                            games: 0,
+                            wins: 0,
+                            draws: 0,
+                        });
+                        moves.len() - 1
+                    }
+                };
+
+                let entry = &mut moves[i];
+                entry.games += 1;
+
+                match record.outcome {
+                    Outcome::Win(winner) if winner == turn => entry.wins += 1,
+                    Outcome::Win(_) => {}
+                    Outcome::Draw => entry.draws += 1,
+                    Outcome::Unfinished => {}
+                }
+            }
+        }
+
+        for moves in book.positions.values_mut() {
+            moves.retain(|x| x.games >= min_games);
+            moves.sort_by_key(|x| Reverse(x.games));
+        }
+
+        book.positions.retain(|_, moves| !moves.is_empty());
+
+        book
+    }
+
+    // Parses lines of "<position> <turn> <move> <games> <wins> <draws>",
+    // where the position is the two board planes as 32 hex digits each
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut book = Self::default();
+
+        for line in s.lines().filter(|x| !x.trim().is_empty()) {
+            let invalid = || format!("Invalid book line: {line}");
+
+            let [position, turn, mv, games, wins, draws] = line
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .try_into()
+                .map_err(|_| invalid())?;
+
+            let plane = |s: &str| -> Result<BitArray<M>, String> {
+                let plane =
+                    u128::from_str_radix(s, 16).map_err(|_| invalid())?;
+
+                Ok(unsafe { mem::transmute::<u128, BitArray<M>>(plane) })
+            };
+
+            if position.len() != 64 {
+                return Err(invalid());
+            }
+
+            let (a, b) = position.split_at(32);
+            let board = BoardState([plane(a)?, plane(b)?]);
+
+            let turn = match turn {
+                "black" => Faction::Black,
+                "white" => Faction::White,
+                _ => return Err(invalid()),
+            };
+
+            let count = |s: &str| s.parse().map_err(|_| invalid());
+
+            let entry = BookMove {
+                mv: from_readable_move(mv).ok_or_else(invalid)?,
+                games: count(games)?,
+                wins: count(wins)?,
+                draws: count(draws)?,
+            };
+
+            if entry.games == 0 || entry.wins + entry.draws > entry.games {
+                return Err(invalid());
+            }
+
+            book.positions.entry((board, turn)).or_default().push(entry);
+        }
+
+        Ok(book)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let s = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {path}: {e}"))?;
+
+        Self::parse(&s)
+    }
+
+    // Makes this the book used by every search
+    pub fn set_startup(self) {
+        if STARTUP_BOOK.set(self).is_err() {
+            panic!("Book already set");
+        }
+    }
+
+    pub fn startup() -> Option<&'static Self> {
+        STARTUP_BOOK.get()
+    }
+
+    // The book moves of a position, turned to match `board`
+    pub fn moves(&self, board: BoardState, turn: Faction) -> Vec<BookMove> {
+        let (canonical, symmetry) = board.canonical();
+
+        self.positions
+            .get(&(canonical, turn))
+            .into_iter()
+            .flatten()
+            .map(|x| BookMove {
+                mv: turn_move(x.mv, inverse_symmetry(symmetry)),
+                ..*x
+            })
+            .collect()
+    }
+
+    // A book move for the position, picked at random with each move weighted
+    // by the points it scored. None for positions not in the book, or where
+    // every move lost.
+    pub fn probe(
+        &self,
+        board: BoardState,
+        turn: Faction,
+        rng: &mut Rng,
+    ) -> Option<BookMove> {
+        let moves = self.moves(board, turn);
+
+        let total = moves.iter().map(BookMove::weight).sum::<u32>();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = rng.below(total as usize) as u32;
+
+        moves.into_iter().find(|x| {
+            if pick < x.weight() {
+                return true;
+            }
+
+            pick -= x.weight();
+            false
+        })
+    }
+}
+
+impl Display for Book {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut positions: Vec<_> = self
+            .positions
+            .iter()
+            .map(|((board, turn), moves)| {
+                let planes: [u128; 2] = unsafe { mem::transmute(board.0) };
+
+                (planes, *turn as usize, moves)
+            })
+            .collect();
+
+        positions.sort_unstable_by_key(|&(planes, turn, _)| (planes, turn));
+
+        for ([a, b], turn, moves) in positions {
+            let turn = [Faction::Black, Faction::White][turn];
+
+            for x in moves {
+                writeln!(
+                    f,
+                    "{a:032x}{b:032x} {} {} {} {} {}",
+                    faction_name(turn),
+                    to_readable_move(x.mv),
+                    x.games,
+                    x.wins,
+                    x.draws,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Builds a book from the game records in `corpus` and writes it to `out_path`
+pub fn generate(corpus: &str, out_path: &str, plies: usize, min_games: u32) {
+    let corpus = fs::read_to_string(corpus).unwrap();
+
+    let records: Vec<_> = corpus
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(|line| GameRecord::parse(line).expect("Invalid game record"))
+        .collect();
+
+    let book = Book::build(&records, plies, min_games);
+
+    println!(
+        "{} games, {} book positions, {} moves",
+        records.len(),
+        book.positions.len(),
+        book.positions.values().map(Vec::len).sum::<usize>(),
+    );
+
+    fs::write(out_path, book.to_string()).unwrap();
+}
@@ -1,13 +1,373 @@
-use ahash::RandomState;
-use hashbrown::HashMap;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::board::{BoardState, Faction, W};
+use crate::{
+    board::{BoardState, Faction, W, neighbours, to_linind, to_readable_move},
+    book::Book,
+    clock::Clock,
+    eval::{EvalKind, EvalWeights, Evaluator, Material},
+    mcts::{Mcts, Playout, probability_to_score},
+    nnue::{Accumulator, Network},
+    rng::Rng,
+    tablebase::{Tablebase, TbValue},
+    trans_table::{Bound, TransTable, TtEntry},
+};
+
+pub type Score = i32;
+
+pub type Move = [[u16; 2]; 2];
+
+pub const PIECE_VALUE: Score = 100;
+
+pub const WIN_SCORE: Score = 1_000_000;
+
+pub const MAX_PLY: u32 = 256;
+
+// Scores further from zero than this are wins at a known distance
+pub const WIN_THRESHOLD: Score = WIN_SCORE - MAX_PLY as Score;
+
+const ASPIRATION_WINDOW: Score = PIECE_VALUE / 2;
+
+const QUIESCENCE_DEPTH: u32 = 8;
+
+// Deepest iteration of a search that is only limited by time
+pub const MAX_DEPTH: u32 = 64;
+
+// Moves the time left on a clock is spread over
+const MOVES_TO_GO: u32 = 30;
+
+const NULL_MOVE_MIN_DEPTH: u32 = 3;
+
+// Below this many pieces the side to move is likely in zugzwang
+const NULL_MOVE_MIN_PIECES: u32 = 6;
+
+const FUTILITY_MARGIN: Score = PIECE_VALUE;
+
+const LMR_MIN_DEPTH: u32 = 3;
+
+const LMR_MIN_MOVE_INDEX: usize = 4;
+
+// Converts between white-relative scores and scores relative to `turn`
+pub fn perspective(turn: Faction) -> Score {
+    match turn {
+        Faction::White => 1,
+        Faction::Black => -1,
+    }
+}
+
+// Number of moves the winning side needs, if the score is a known win
+pub fn moves_to_win(score: Score) -> Option<(Faction, u32)> {
+    if score > WIN_THRESHOLD {
+        Some((Faction::White, ((WIN_SCORE - score) as u32).div_ceil(2)))
+    } else if score < -WIN_THRESHOLD {
+        Some((Faction::Black, ((WIN_SCORE + score) as u32).div_ceil(2)))
+    } else {
+        None
+    }
+}
+
+pub fn describe_score(score: Score) -> String {
+    match moves_to_win(score) {
+        Some((winner, n)) => format!("{winner:?} wins in {n}"),
+        None => format!("score: {score:+}"),
+    }
+}
+
+pub fn format_pv(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|&mv| to_readable_move(mv))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Win scores are stored relative to the position they were found in, so they
+// stay correct when the same position is reached at a different ply.
+fn score_to_tt(score: Score, ply: u32) -> Score {
+    if score > WIN_THRESHOLD {
+        score + ply as Score
+    } else if score < -WIN_THRESHOLD {
+        score - ply as Score
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: Score, ply: u32) -> Score {
+    if score > WIN_THRESHOLD {
+        score - ply as Score
+    } else if score < -WIN_THRESHOLD {
+        score + ply as Score
+    } else {
+        score
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions {
+    pub pvs: bool,
+    pub aspiration: bool,
+    pub quiescence: bool,
+    pub null_move: bool,
+    pub lmr: bool,
+    pub futility: bool,
+    pub threads: usize,
+    // Transposition table size in MiB
+    pub hash_size: usize,
+    // Share transposition table entries between turned or mirrored copies of
+    // a position
+    pub symmetry: bool,
+    // Play moves from the opening book loaded with `--book`
+    pub book: bool,
+    pub eval: EvalKind,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            pvs: true,
+            aspiration: true,
+            quiescence: true,
+            // Off until a match shows they gain anything
+            null_move: false,
+            lmr: false,
+            futility: false,
+            threads: 1,
+            hash_size: 64,
+            symmetry: false,
+            book: true,
+            eval: EvalKind::Tafl(EvalWeights::startup()),
+        }
+    }
+}
+
+impl SearchOptions {
+    // Applies a setting like "lmr" or "threads=4"
+    pub fn set(&mut self, setting: &str) -> Result<(), String> {
+        let parse = |n: &str| {
+            n.parse().map_err(|_| format!("Invalid value in {setting}"))
+        };
+
+        match setting.split_once('=') {
+            Some(("threads", n)) => self.threads = parse(n)?,
+            Some(("hash", n)) => self.hash_size = parse(n)?,
+            Some(("eval", "material")) => self.eval = EvalKind::Material,
+            Some(("eval", "nnue")) => {
+                if !Network::startup_loaded() {
+                    return Err(
+                        "eval=nnue needs a network loaded with --nnue".into()
+                    );
+                }
+
+                self.eval = EvalKind::Nnue
+            }
+            Some(("eval", "tafl")) => {
+                self.eval = EvalKind::Tafl(EvalWeights::startup())
+            }
+            Some(("weights", path)) => {
+                self.eval = EvalKind::Tafl(EvalWeights::load(path)?)
+            }
+            _ => match setting {
+                "nopvs" => self.pvs = false,
+                "noaspiration" => self.aspiration = false,
+                "noquiescence" => self.quiescence = false,
+                "nonullmove" => self.null_move = false,
+                "nolmr" => self.lmr = false,
+                "nofutility" => self.futility = false,
+                "nullmove" => self.null_move = true,
+                "lmr" => self.lmr = true,
+                "futility" => self.futility = true,
+                "symmetry" => self.symmetry = true,
+                "nobook" => self.book = false,
+                _ => return Err(format!("Unknown search option: {setting}")),
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    AlphaBeta,
+    Mcts,
+}
+
+// How a bot plays: which engine, how deep or how long to search, and with
+// which options
+#[derive(Debug, Clone, Copy)]
+pub struct BotConfig {
+    pub engine: Engine,
+    pub depth: u32,
+    // Playouts per move for MCTS
+    pub playouts: u64,
+    pub playout: Playout,
+    pub time: Option<Duration>,
+    pub options: SearchOptions,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            engine: Engine::AlphaBeta,
+            depth: 3,
+            playouts: 10_000,
+            playout: Playout::Eval,
+            time: None,
+            options: SearchOptions::default(),
+        }
+    }
+}
+
+impl BotConfig {
+    // Parses comma separated settings like "depth=4,time=1.5,lmr", with the
+    // time in seconds per move. Without a depth, a timed bot searches as deep
+    // as its time allows. MCTS is chosen with "engine=mcts", and takes
+    // "playouts=N" and "playout=random|eval" instead of a depth.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut depth = None;
+        let mut playouts = None;
+
+        for setting in s.split(',').filter(|x| !x.is_empty()) {
+            match setting.split_once('=') {
+                Some(("depth", n)) => {
+                    depth = Some(
+                        n.parse().map_err(|_| format!("Invalid depth: {n}"))?,
+                    )
+                }
+                Some(("engine", "alphabeta")) => {
+                    config.engine = Engine::AlphaBeta
+                }
+                Some(("engine", "mcts")) => config.engine = Engine::Mcts,
+                Some(("playouts", n)) => {
+                    playouts = Some(
+                        n.parse()
+                            .map_err(|_| format!("Invalid playouts: {n}"))?,
+                    )
+                }
+                Some(("playout", "random")) => config.playout = Playout::Random,
+                Some(("playout", "eval")) => config.playout = Playout::Eval,
+                // Negative, infinite and NaN times are rejected here
+                Some(("time", t)) => {
+                    config.time = Some(
+                        t.parse()
+                            .ok()
+                            .and_then(|t| Duration::try_from_secs_f64(t).ok())
+                            .ok_or_else(|| format!("Invalid time: {t}"))?,
+                    )
+                }
+                _ => config.options.set(setting)?,
+            }
+        }
+
+        config.depth = depth.unwrap_or(match config.time {
+            Some(_) => MAX_DEPTH,
+            None => config.depth,
+        });
+
+        config.playouts = playouts.unwrap_or(match config.time {
+            Some(_) => u64::MAX,
+            None => config.playouts,
+        });
+
+        Ok(config)
+    }
+
+    // This config with its time per move cut to what `clock` allows: a share
+    // of the time left plus most of what the clock adds per move, keeping a
+    // margin for the search overrunning its deadline
+    pub fn with_clock(self, clock: &Clock) -> Self {
+        let budget = (clock.remaining() / MOVES_TO_GO
+            + clock.per_move() * 9 / 10)
+            .min(clock.spendable() * 9 / 10);
+
+        Self {
+            time: Some(self.time.map_or(budget, |time| time.min(budget))),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    // The principal variation, starting with the best move
+    pub pv: Vec<Move>,
+    // Relative to white
+    pub score: Score,
+    pub depth: u32,
+    pub nodes: u64,
+    pub time: Duration,
+}
+
+impl SearchResult {
+    pub fn best_move(&self) -> Move {
+        self.pv[0]
+    }
+
+    // Engine protocol style `info` line, with the score relative to `turn`
+    pub fn info_line(&self, turn: Faction) -> String {
+        let score = self.score * perspective(turn);
+
+        let score = match moves_to_win(score) {
+            Some((Faction::White, n)) => format!("mate {n}"),
+            Some((Faction::Black, n)) => format!("mate -{n}"),
+            None => format!("cp {score}"),
+        };
+
+        format!(
+            "info depth {} score {score} nodes {} time {} nps {} pv {}",
+            self.depth,
+            self.nodes,
+            self.time.as_millis(),
+            (self.nodes as f64 / self.time.as_secs_f64()) as u64,
+            format_pv(&self.pv),
+        )
+    }
+}
+
+pub struct Searcher {
+    pub trans_table: Arc<TransTable>,
+    pub options: SearchOptions,
+    pub evaluator: Arc<dyn Evaluator>,
+    network: Option<&'static Network>,
+    // Hidden layer of the network for the position at each ply
+    accumulators: Vec<Accumulator>,
+    tablebase: Option<&'static Tablebase>,
+    book: Option<&'static Book>,
+    // Picks between book moves
+    rng: Rng,
+    pub nodes: u64,
+    // The part of `nodes` searched by Lazy SMP helper threads
+    pub helper_nodes: u64,
+    // Aborts the search once set, keeping the last finished iteration
+    pub stop: Arc<AtomicBool>,
+    // Stops the search like `stop` once passed
+    pub deadline: Option<Instant>,
+    timed_out: bool,
+    // Triangular table of principal variations, indexed by ply
+    pv_table: Vec<Vec<Move>>,
+    // Whether the move made at each ply was a null move
+    null_move_at: Vec<bool>,
+    // The first iteration always finishes, so there is a move to return
+    can_stop: bool,
+    // Lazy SMP helpers search deeper than the main thread to spread out
+    depth_offset: u32,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::new(SearchOptions::default())
+    }
+}
 
 impl BoardState {
-    pub fn all_moves(
-        &self,
-        turn: Faction,
-    ) -> impl Iterator<Item = [[u16; 2]; 2]> {
+    pub fn all_moves(&self, turn: Faction) -> impl Iterator<Item = Move> {
         self.select_faction(turn)
             .trues_iter()
             .map(|i| [(i / W) as u16, (i % W) as u16])
@@ -20,174 +380,1049 @@ impl BoardState {
             })
     }
 
-    fn zeroeval(self) -> f64 {
-        self.whites().count_ones() as f64 - self.blacks().count_ones() as f64
-    }
-
     // Naive minimax
-    pub fn _minimax(self, turn: Faction, depth: u32) -> f64 {
+    pub fn _minimax(self, turn: Faction, depth: u32, ply: u32) -> Score {
         if depth == 0 {
-            return self.zeroeval();
+            return Material.evaluate(self);
         }
 
         let evals_iter = self.all_moves(turn).map(|[from, to]| {
             let mut new_board = self;
             if new_board.do_move(from, to) {
-                match turn {
-                    Faction::Black => -f64::INFINITY,
-                    Faction::White => f64::INFINITY,
-                }
+                (WIN_SCORE - (ply + 1) as Score) * perspective(turn)
             } else {
-                new_board._minimax(turn.other_faction(), depth - 1)
+                new_board._minimax(turn.other_faction(), depth - 1, ply + 1)
             }
         });
 
         match turn {
-            Faction::Black => evals_iter.min_by(|a, b| a.total_cmp(b)),
-            Faction::White => evals_iter.max_by(|a, b| a.total_cmp(b)),
+            Faction::Black => evals_iter.min(),
+            Faction::White => evals_iter.max(),
         }
         .expect("No legal moves!")
     }
+}
+
+impl Searcher {
+    pub fn new(options: SearchOptions) -> Self {
+        let evaluator = options.eval.evaluator();
+
+        Self {
+            trans_table: Arc::new(TransTable::new(
+                options.hash_size,
+                options.symmetry,
+            )),
+            options,
+            network: evaluator.network(),
+            evaluator,
+            accumulators: Vec::new(),
+            tablebase: Tablebase::startup(),
+            book: Book::startup(),
+            rng: Rng::new(
+                SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos() as u64
+            ),
+            nodes: 0,
+            helper_nodes: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            timed_out: false,
+            pv_table: vec![Vec::new(); MAX_PLY as usize + 1],
+            null_move_at: vec![false; MAX_PLY as usize + 1],
+            can_stop: false,
+            depth_offset: 0,
+        }
+    }
+
+    // A searcher for pondering in another thread, sharing the transposition
+    // table but stopped on its own
+    pub fn ponderer(&self) -> Self {
+        self.helper(Arc::new(AtomicBool::new(false)), 0)
+    }
+
+    // A searcher for a helper thread, sharing the transposition table
+    fn helper(&self, stop: Arc<AtomicBool>, depth_offset: u32) -> Self {
+        Self {
+            trans_table: self.trans_table.clone(),
+            options: self.options,
+            evaluator: self.evaluator.clone(),
+            network: self.network,
+            accumulators: Vec::new(),
+            tablebase: self.tablebase,
+            book: None,
+            rng: Rng::new(0),
+            nodes: 0,
+            helper_nodes: 0,
+            stop,
+            deadline: None,
+            timed_out: false,
+            pv_table: vec![Vec::new(); MAX_PLY as usize + 1],
+            null_move_at: vec![false; MAX_PLY as usize + 1],
+            can_stop: false,
+            depth_offset,
+        }
+    }
+
+    // Static evaluation relative to `turn`, using the accumulator kept for
+    // `ply` with a network
+    fn evaluate(&self, board: BoardState, turn: Faction, ply: u32) -> Score {
+        let score = match self.network {
+            Some(network) => network.output(&self.accumulators[ply as usize]),
+            None => self.evaluator.evaluate(board),
+        };
+
+        score * perspective(turn)
+    }
+
+    // Sets up the accumulator for the root of a search
+    fn refresh_accumulator(&mut self, board: BoardState) {
+        if let Some(network) = self.network {
+            self.accumulators.clear();
+            self.accumulators
+                .resize(MAX_PLY as usize + 1, network.refresh(board));
+        }
+    }
+
+    // Updates the accumulator for `ply + 1` after a move from `board` at
+    // `ply` to `new_board`
+    fn make_move(
+        &mut self,
+        ply: u32,
+        board: BoardState,
+        new_board: BoardState,
+    ) {
+        if let Some(network) = self.network {
+            let ply = ply as usize;
+
+            self.accumulators[ply + 1] =
+                network.update(&self.accumulators[ply], board, new_board);
+        }
+    }
+
+    // Whether quiescence search agrees with the static evaluation, so the
+    // position has no captures or king threats worth playing
+    pub fn is_quiet(&mut self, board: BoardState, turn: Faction) -> bool {
+        self.refresh_accumulator(board);
 
-    pub fn alphabeta(
-        self,
+        let score = self.quiesce(
+            board,
+            turn,
+            0,
+            QUIESCENCE_DEPTH,
+            -WIN_SCORE,
+            WIN_SCORE,
+        );
+
+        score == self.evaluate(board, turn, 0)
+    }
+
+    // Exact score relative to `turn` from the tablebase, for positions below
+    // the root
+    fn probe_tablebase(
+        &self,
+        board: BoardState,
+        turn: Faction,
+        ply: u32,
+    ) -> Option<Score> {
+        if ply == 0 {
+            return None;
+        }
+
+        Some(match self.tablebase?.probe(board, turn)? {
+            TbValue::Win(n) => WIN_SCORE - (ply + n) as Score,
+            TbValue::Loss(n) => -(WIN_SCORE - (ply + n) as Score),
+            TbValue::Draw => 0,
+        })
+    }
+
+    fn stopped(&self) -> bool {
+        self.can_stop && (self.timed_out || self.stop.load(Ordering::Relaxed))
+    }
+
+    // Counts a node. Every node is counted here, so the deadline is checked
+    // every 1024 nodes whichever kind of search they come from.
+    fn count_node(&mut self) {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(1024)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.timed_out = true;
+        }
+    }
+
+    // Moves of `turn` with the transposition table move tried first, followed
+    // by king moves and moves next to an opponent piece, which are the only
+    // ones that can win or capture.
+    fn ordered_moves(
+        &self,
+        board: BoardState,
+        turn: Faction,
+        tt_move: Option<Move>,
+    ) -> Vec<Move> {
+        let mut moves: Vec<_> = board.all_moves(turn).collect();
+
+        let targets = neighbours(board.select_faction(turn.other_faction()));
+        let king = board.king_coord();
+
+        moves.sort_by_key(|&[from, to]| {
+            !(targets[to_linind(to).unwrap()] || Some(from) == king)
+        });
+
+        if let Some(i) =
+            tt_move.and_then(|m| moves.iter().position(|&x| x == m))
+        {
+            moves[..=i].rotate_right(1);
+        }
+
+        moves
+    }
+
+    // Negamax with principal variation search. The score is relative to
+    // `turn`.
+    fn negamax(
+        &mut self,
+        board: BoardState,
         turn: Faction,
         depth: u32,
-        mut alpha: f64,
-        mut beta: f64,
-        trans_table: &mut HashMap<Self, (u32, f64), RandomState>,
-    ) -> f64 {
-        if depth == 0 {
-            return self.zeroeval();
+        ply: u32,
+        mut alpha: Score,
+        beta: Score,
+    ) -> Score {
+        if self.stopped() {
+            return 0;
+        }
+
+        self.count_node();
+        self.pv_table[ply as usize].clear();
+
+        if let Some(score) = self.probe_tablebase(board, turn, ply) {
+            return score;
         }
 
-        if let Some(&(d, s)) = trans_table.get(&self) {
-            if d >= depth {
-                return s;
+        if depth == 0 {
+            if self.options.quiescence {
+                return self.quiesce(
+                    board,
+                    turn,
+                    ply,
+                    QUIESCENCE_DEPTH,
+                    alpha,
+                    beta,
+                );
             }
+
+            return self.evaluate(board, turn, ply);
         }
 
-        let it = self.all_moves(turn).map(|[from, to]| {
-            let mut new_board = self;
-            (new_board.do_move(from, to), new_board)
-        });
+        let mut tt_move = None;
 
-        let score = match turn {
-            Faction::White => {
-                let mut score = -f64::INFINITY;
-
-                for (won, board) in it {
-                    score = score.max(if won {
-                        f64::INFINITY
-                    } else {
-                        board.alphabeta(
-                            turn.other_faction(),
-                            depth - 1,
-                            alpha,
-                            beta,
-                            trans_table,
-                        )
-                    });
-
-                    alpha = alpha.max(score);
-
-                    if score >= beta {
-                        break;
-                    }
+        if let Some(entry) = self.trans_table.get(board, turn) {
+            tt_move = entry.best_move;
+
+            if entry.depth >= depth {
+                let score = score_from_tt(entry.score, ply);
+
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
                 }
+            }
+        }
+
+        let pv_node = beta - alpha > 1;
+        let static_eval = self.evaluate(board, turn, ply);
+        let king_threat = board.king_escapes().count_ones() > 0;
+        let opponent = turn.other_faction();
 
-                score
+        // Passing is not legal in tafl, so null move pruning is left out
+        // where having to move can hurt: when the king threatens to escape,
+        // with few pieces left, and right after another null move.
+        if self.options.null_move
+            && !pv_node
+            && depth >= NULL_MOVE_MIN_DEPTH
+            && !king_threat
+            && static_eval >= beta
+            && board.select_faction(turn).count_ones() >= NULL_MOVE_MIN_PIECES
+            && !self.null_move_at[ply as usize - 1]
+        {
+            let reduction = if depth > 6 { 3 } else { 2 };
+
+            self.null_move_at[ply as usize] = true;
+            self.make_move(ply, board, board);
+            let score = -self.negamax(
+                board,
+                opponent,
+                depth - 1 - reduction,
+                ply + 1,
+                -beta,
+                -beta + 1,
+            );
+            self.null_move_at[ply as usize] = false;
+
+            if self.stopped() {
+                return 0;
             }
-            Faction::Black => {
-                let mut score = f64::INFINITY;
 
-                for (won, board) in it {
-                    score = score.min(if won {
-                        -f64::INFINITY
-                    } else {
-                        board.alphabeta(
-                            turn.other_faction(),
-                            depth - 1,
-                            alpha,
-                            beta,
-                            trans_table,
-                        )
-                    });
+            if score >= beta {
+                // A win found after passing is not a proven win
+                return score.min(WIN_THRESHOLD);
+            }
+        }
 
-                    beta = beta.min(score);
+        // Close to the leaves, quiet moves are not expected to make up for
+        // being this far below alpha.
+        let futile = self.options.futility
+            && !pv_node
+            && depth <= 2
+            && !king_threat
+            && alpha.abs() < WIN_THRESHOLD
+            && static_eval + FUTILITY_MARGIN * depth as Score <= alpha;
 
-                    if score <= alpha {
-                        break;
-                    }
+        let king = board.king_coord();
+        let opponent_pieces = board.select_faction(opponent).count_ones();
+
+        let original_alpha = alpha;
+
+        // A side without legal moves has lost
+        let mut best_score = -(WIN_SCORE - ply as Score);
+        let mut best_move = None;
+
+        for (i, [from, to]) in self
+            .ordered_moves(board, turn, tt_move)
+            .into_iter()
+            .enumerate()
+        {
+            let mut new_board = board;
+
+            let won = new_board.do_move(from, to);
+
+            let quiet = !won
+                && Some(from) != king
+                && new_board.select_faction(opponent).count_ones()
+                    == opponent_pieces;
+
+            if futile && quiet {
+                best_score = best_score.max(static_eval);
+                continue;
+            }
+
+            self.make_move(ply, board, new_board);
+
+            // Late quiet moves get a reduced null window search first, and
+            // are only searched fully if that beats alpha.
+            let reduction = if self.options.lmr
+                && !pv_node
+                && quiet
+                && !king_threat
+                && depth >= LMR_MIN_DEPTH
+                && i >= LMR_MIN_MOVE_INDEX
+            {
+                if depth >= 6 && i >= 3 * LMR_MIN_MOVE_INDEX {
+                    2
+                } else {
+                    1
                 }
+            } else {
+                0
+            };
+
+            let score = if won {
+                WIN_SCORE - (ply + 1) as Score
+            } else if reduction > 0 {
+                let score = -self.negamax(
+                    new_board,
+                    opponent,
+                    depth - 1 - reduction,
+                    ply + 1,
+                    -alpha - 1,
+                    -alpha,
+                );
+
+                if score > alpha && !self.stopped() {
+                    self.search_child(
+                        new_board, turn, depth, ply, alpha, beta, i,
+                    )
+                } else {
+                    score
+                }
+            } else {
+                self.search_child(new_board, turn, depth, ply, alpha, beta, i)
+            };
 
-                score
+            if self.stopped() {
+                return 0;
             }
-        };
 
-        *trans_table.entry(self).or_insert((depth, score)) = (depth, score);
+            if score > best_score {
+                best_score = score;
+                best_move = Some([from, to]);
+            }
 
-        score
+            if score > alpha {
+                self.update_pv(ply, [from, to], won);
+            }
+
+            alpha = alpha.max(score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        self.store(
+            board,
+            turn,
+            depth,
+            ply,
+            best_score,
+            original_alpha,
+            beta,
+            best_move,
+        );
+
+        best_score
     }
 
-    pub fn best_move(
-        self,
+    // Makes `mv` followed by the principal variation of the child the
+    // principal variation at `ply`.
+    fn update_pv(&mut self, ply: u32, mv: Move, won: bool) {
+        let ply = ply as usize;
+
+        let [pv, child_pv] =
+            self.pv_table.get_disjoint_mut([ply, ply + 1]).unwrap();
+
+        pv.clear();
+        pv.push(mv);
+
+        if !won {
+            pv.extend_from_slice(child_pv);
+        }
+    }
+
+    // Extends `pv` with best moves from the transposition table, which can
+    // cut the principal variation short.
+    fn extend_pv(
+        &self,
+        mut board: BoardState,
+        mut turn: Faction,
+        pv: &mut Vec<Move>,
+        length: usize,
+    ) {
+        for &[from, to] in pv.iter() {
+            if board.do_move(from, to) {
+                return;
+            }
+
+            turn = turn.other_faction();
+        }
+
+        while pv.len() < length {
+            let Some([from, to]) = self
+                .trans_table
+                .get(board, turn)
+                .and_then(|entry| entry.best_move)
+                .filter(|mv| board.all_moves(turn).any(|x| x == *mv))
+            else {
+                break;
+            };
+
+            pv.push([from, to]);
+
+            if board.do_move(from, to) {
+                break;
+            }
+
+            turn = turn.other_faction();
+        }
+    }
+
+    // Extends captures and king moves that reach or threaten a corner until
+    // the position is quiet. The score is relative to `turn`.
+    fn quiesce(
+        &mut self,
+        board: BoardState,
         turn: Faction,
+        ply: u32,
         depth: u32,
-        trans_table: &mut HashMap<Self, (u32, f64), RandomState>,
-    ) -> ([[u16; 2]; 2], f64) {
-        let mut alpha = -f64::INFINITY;
-        let mut beta = f64::INFINITY;
+        mut alpha: Score,
+        beta: Score,
+    ) -> Score {
+        if self.stopped() {
+            return 0;
+        }
 
-        let mut best_move = None;
-        let mut score = match turn {
-            Faction::Black => f64::INFINITY,
-            Faction::White => -f64::INFINITY,
+        self.count_node();
+
+        if let Some(score) = self.probe_tablebase(board, turn, ply) {
+            return score;
+        }
+
+        let king_threat = board.king_escapes().count_ones() > 0;
+
+        if king_threat && turn == Faction::White {
+            return WIN_SCORE - (ply + 1) as Score;
+        }
+
+        let stand_pat = self.evaluate(board, turn, ply);
+
+        if depth == 0 {
+            return stand_pat;
+        }
+
+        // Black has to block the king's path to the corner, so standing pat
+        // is not an option.
+        let evading = king_threat;
+
+        let mut best_score = if evading {
+            -(WIN_SCORE - ply as Score)
+        } else {
+            stand_pat
         };
 
-        for [from, to] in self.all_moves(turn) {
-            if best_move.is_none() {
-                best_move = Some([from, to])
+        if best_score >= beta {
+            return best_score;
+        }
+
+        alpha = alpha.max(best_score);
+
+        let opponent = turn.other_faction();
+        let opponent_pieces = board.select_faction(opponent).count_ones();
+
+        // Only moves next to an opponent piece can capture
+        let targets = neighbours(board.select_faction(opponent));
+        let king = board.king_coord();
+
+        for [from, to] in board.all_moves(turn) {
+            if !evading
+                && !targets[to_linind(to).unwrap()]
+                && Some(from) != king
+            {
+                continue;
             }
 
-            let mut new_board = self;
+            let mut new_board = board;
 
-            let local_score = if new_board.do_move(from, to) {
-                match turn {
-                    Faction::Black => -f64::INFINITY,
-                    Faction::White => f64::INFINITY,
-                }
+            if new_board.do_move(from, to) {
+                return WIN_SCORE - (ply + 1) as Score;
+            }
+
+            let capture = new_board.select_faction(opponent).count_ones()
+                < opponent_pieces;
+
+            let king_threat = new_board.king_coord() == Some(to)
+                && new_board.king_escapes().count_ones() > 0;
+
+            if !(evading || capture || king_threat) {
+                continue;
+            }
+
+            self.make_move(ply, board, new_board);
+
+            let score = -self.quiesce(
+                new_board,
+                opponent,
+                ply + 1,
+                depth - 1,
+                -beta,
+                -alpha,
+            );
+
+            if self.stopped() {
+                return 0;
+            }
+
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+
+    // Searches the child at `board` (already moved into by `turn`), using a
+    // null window for every move but the first when PVS is enabled.
+    #[allow(clippy::too_many_arguments)]
+    fn search_child(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        ply: u32,
+        alpha: Score,
+        beta: Score,
+        move_index: usize,
+    ) -> Score {
+        let next = turn.other_faction();
+
+        if move_index == 0 || !self.options.pvs {
+            return -self.negamax(
+                board,
+                next,
+                depth - 1,
+                ply + 1,
+                -beta,
+                -alpha,
+            );
+        }
+
+        let score =
+            -self.negamax(board, next, depth - 1, ply + 1, -alpha - 1, -alpha);
+
+        if score > alpha && score < beta {
+            -self.negamax(board, next, depth - 1, ply + 1, -beta, -alpha)
+        } else {
+            score
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn store(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        ply: u32,
+        score: Score,
+        alpha: Score,
+        beta: Score,
+        best_move: Option<Move>,
+    ) {
+        let bound = if score <= alpha {
+            Bound::Upper
+        } else if score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+
+        self.trans_table.insert(board, turn, TtEntry {
+            depth,
+            score: score_to_tt(score, ply),
+            bound,
+            best_move,
+        });
+    }
+
+    // Searches every root move except `excluded` to `depth` further plies,
+    // leaving the principal variation at ply 0. The score is relative to
+    // `turn`.
+    fn search_root(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        mut alpha: Score,
+        beta: Score,
+        excluded: &[Move],
+    ) -> Score {
+        self.count_node();
+        self.refresh_accumulator(board);
+
+        let tt_move = self
+            .trans_table
+            .get(board, turn)
+            .and_then(|entry| entry.best_move);
+
+        let original_alpha = alpha;
+
+        let mut best_score = -WIN_SCORE;
+        let mut best_move = None;
+
+        for (i, [from, to]) in self
+            .ordered_moves(board, turn, tt_move)
+            .into_iter()
+            .filter(|mv| !excluded.contains(mv))
+            .enumerate()
+        {
+            let mut new_board = board;
+
+            let won = new_board.do_move(from, to);
+            self.make_move(0, board, new_board);
+
+            let score = if won {
+                WIN_SCORE - 1
             } else {
-                new_board.alphabeta(
-                    turn.other_faction(),
-                    depth,
-                    alpha,
-                    beta,
-                    trans_table,
-                )
+                self.search_child(new_board, turn, depth + 1, 0, alpha, beta, i)
             };
 
-            match turn {
-                Faction::Black => {
-                    if local_score < score {
-                        best_move = Some([from, to]);
-                        score = local_score;
-                    }
-                    beta = beta.min(score);
-                }
-                Faction::White => {
-                    if local_score > score {
-                        best_move = Some([from, to]);
-                        score = local_score;
+            if self.stopped() {
+                return 0;
+            }
+
+            if best_move.is_none() || score > best_score {
+                best_score = score;
+                best_move = Some([from, to]);
+            }
+
+            if best_move == Some([from, to]) || score > alpha {
+                self.update_pv(0, [from, to], won);
+            }
+
+            alpha = alpha.max(score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        assert!(best_move.is_some(), "No legal moves!");
+
+        // The root entry should describe the full position, not what is left
+        // after excluding moves
+        if excluded.is_empty() {
+            self.store(
+                board,
+                turn,
+                depth + 1,
+                0,
+                best_score,
+                original_alpha,
+                beta,
+                best_move,
+            );
+        }
+
+        best_score
+    }
+
+    // One iteration of iterative deepening, searched in an aspiration window
+    // around `previous` when given.
+    fn search_iteration(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        previous: Option<Score>,
+        excluded: &[Move],
+    ) -> Score {
+        let Some(previous) = previous.filter(|_| self.options.aspiration)
+        else {
+            return self.search_root(
+                board, turn, depth, -WIN_SCORE, WIN_SCORE, excluded,
+            );
+        };
+
+        let mut delta = ASPIRATION_WINDOW;
+        let mut alpha = (previous - delta).max(-WIN_SCORE);
+        let mut beta = (previous + delta).min(WIN_SCORE);
+
+        loop {
+            let score =
+                self.search_root(board, turn, depth, alpha, beta, excluded);
+
+            delta *= 4;
+
+            if self.stopped() {
+                return score;
+            } else if score <= alpha && alpha > -WIN_SCORE {
+                alpha = (score - delta).max(-WIN_SCORE);
+            } else if score >= beta && beta < WIN_SCORE {
+                beta = (score + delta).min(WIN_SCORE);
+            } else {
+                return score;
+            }
+        }
+    }
+
+    fn iteration_result(
+        &self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        score: Score,
+        start_nodes: u64,
+        start_time: Instant,
+    ) -> SearchResult {
+        let mut pv = self.pv_table[0].clone();
+        self.extend_pv(board, turn, &mut pv, depth as usize + 1);
+
+        SearchResult {
+            pv,
+            score: score * perspective(turn),
+            depth,
+            nodes: self.nodes - start_nodes,
+            time: start_time.elapsed(),
+        }
+    }
+
+    // Runs `f` on this thread while `threads - 1` helpers search the same
+    // position (Lazy SMP). The helpers' results only reach the main thread
+    // through the shared transposition table.
+    fn with_helpers<R>(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        self.trans_table.new_search();
+
+        if self.options.threads <= 1 {
+            return f(self);
+        }
+
+        let helper_stop = Arc::new(AtomicBool::new(false));
+
+        let helpers: Vec<_> = (1..self.options.threads)
+            .map(|i| self.helper(helper_stop.clone(), i as u32 % 2))
+            .collect();
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = helpers
+                .into_iter()
+                .map(|mut helper| {
+                    scope.spawn(move || {
+                        helper.iterate(board, turn, depth + 1, |_| {});
+                        helper.nodes
+                    })
+                })
+                .collect();
+
+            let result = f(self);
+
+            helper_stop.store(true, Ordering::Relaxed);
+
+            for handle in handles {
+                let nodes = handle.join().unwrap();
+
+                self.nodes += nodes;
+                self.helper_nodes += nodes;
+            }
+
+            result
+        })
+    }
+
+    // Iterative deepening up to the root move plus `depth` plies, with
+    // aspiration windows around the score of the previous iteration.
+    // `report` is called with the result of each finished iteration.
+    pub fn search(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        report: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let start_nodes = self.nodes;
+
+        let mut result = self.with_helpers(board, turn, depth, |searcher| {
+            searcher.iterate(board, turn, depth, report)
+        });
+
+        result.nodes = self.nodes - start_nodes;
+
+        result
+    }
+
+    fn iterate(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        mut report: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        let start_time = Instant::now();
+        let start_nodes = self.nodes;
+
+        self.can_stop = false;
+        self.timed_out = false;
+
+        let mut result: Option<SearchResult> = None;
+
+        for d in self.depth_offset..=depth + self.depth_offset {
+            // The next iteration is unlikely to finish if this one took more
+            // than half the time
+            if self.can_stop
+                && self.deadline.is_some_and(|deadline| {
+                    Instant::now() > deadline - (deadline - start_time) / 2
+                })
+            {
+                break;
+            }
+
+            let previous = result.as_ref().map(|r| r.score * perspective(turn));
+
+            let score = self.search_iteration(board, turn, d, previous, &[]);
+
+            if self.stopped() {
+                break;
+            }
+
+            let iteration = self.iteration_result(
+                board,
+                turn,
+                d,
+                score,
+                start_nodes,
+                start_time,
+            );
+
+            report(&iteration);
+
+            result = Some(iteration);
+
+            self.can_stop = true;
+        }
+
+        result.unwrap()
+    }
+
+    // Like `search`, but finds the best `lines` root moves, each with its own
+    // score and principal variation. The results are ranked best first for
+    // `turn`.
+    pub fn search_multi_pv(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+        lines: usize,
+        mut report: impl FnMut(&[SearchResult]),
+    ) -> Vec<SearchResult> {
+        let start_time = Instant::now();
+        let start_nodes = self.nodes;
+
+        let lines = lines.min(board.all_moves(turn).count()).max(1);
+
+        let mut results: Vec<SearchResult> = Vec::new();
+
+        self.with_helpers(board, turn, depth, |searcher| {
+            searcher.can_stop = false;
+            searcher.timed_out = false;
+
+            for d in 0..=depth {
+                let mut iteration: Vec<SearchResult> = Vec::new();
+
+                for k in 0..lines {
+                    let previous =
+                        results.get(k).map(|r| r.score * perspective(turn));
+
+                    let excluded: Vec<_> =
+                        iteration.iter().map(|r| r.best_move()).collect();
+
+                    let score = searcher
+                        .search_iteration(board, turn, d, previous, &excluded);
+
+                    if searcher.stopped() {
+                        return;
                     }
-                    alpha = alpha.max(score);
+
+                    iteration.push(searcher.iteration_result(
+                        board,
+                        turn,
+                        d,
+                        score,
+                        start_nodes,
+                        start_time,
+                    ));
                 }
+
+                iteration.sort_by_key(|r| -r.score * perspective(turn));
+
+                report(&iteration);
+
+                results = iteration;
+
+                searcher.can_stop = true;
             }
+        });
+
+        results
+    }
+
+    // A move from the opening book, scored by how well it did in the games
+    // the book was built from
+    fn book_move(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+    ) -> Option<SearchResult> {
+        if !self.options.book {
+            return None;
+        }
+
+        let entry = self.book?.probe(board, turn, &mut self.rng)?;
+
+        if !board.all_moves(turn).any(|mv| mv == entry.mv) {
+            return None;
         }
 
-        *trans_table.entry(self).or_insert((depth + 1, score)) =
-            (depth + 1, score);
+        Some(SearchResult {
+            pv: vec![entry.mv],
+            score: probability_to_score(entry.win_rate()) * perspective(turn),
+            depth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+        })
+    }
 
-        (best_move.unwrap(), score)
+    pub fn best_move(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        depth: u32,
+    ) -> SearchResult {
+        if let Some(result) = self.book_move(board, turn) {
+            return result;
+        }
+
+        self.search(board, turn, depth, |_| {})
+    }
+
+    // Searches to the depth of `config`, or until its time runs out
+    pub fn think(
+        &mut self,
+        config: &BotConfig,
+        board: BoardState,
+        turn: Faction,
+    ) -> SearchResult {
+        self.deadline = config.time.map(|time| Instant::now() + time);
+
+        let result = self.best_move(board, turn, config.depth);
+
+        self.deadline = None;
+
+        result
+    }
+}
+
+// Either engine, set up to play with a `BotConfig`
+pub enum Bot {
+    AlphaBeta(Searcher),
+    Mcts(Mcts),
+}
+
+impl Bot {
+    pub fn new(config: &BotConfig) -> Self {
+        match config.engine {
+            Engine::AlphaBeta => Self::AlphaBeta(Searcher::new(config.options)),
+            Engine::Mcts => {
+                Self::Mcts(Mcts::new(&config.options, config.playout))
+            }
+        }
+    }
+
+    // Fails if `turn` has no legal moves
+    pub fn think(
+        &mut self,
+        config: &BotConfig,
+        board: BoardState,
+        turn: Faction,
+    ) -> Result<SearchResult, String> {
+        match self {
+            Self::AlphaBeta(_) if board.all_moves(turn).next().is_none() => {
+                Err(format!("{turn:?} has no legal moves"))
+            }
+            Self::AlphaBeta(searcher) => {
+                Ok(searcher.think(config, board, turn))
+            }
+            Self::Mcts(mcts) => mcts.think(config, board, turn),
+        }
     }
 }
@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+// How much time each side gets for the game
+#[derive(Debug, Clone, Copy)]
+pub enum TimeControl {
+    // A base time with an increment added after every move
+    Fischer {
+        base: Duration,
+        increment: Duration,
+    },
+    // A base time, then `periods` periods of `period` each. A period is only
+    // used up by a move that takes longer than it.
+    Byoyomi {
+        base: Duration,
+        period: Duration,
+        periods: u32,
+    },
+}
+
+impl TimeControl {
+    // Parses "5+3" for 5 minutes plus 3 seconds per move, or "10+30x5" for
+    // 10 minutes then 5 byo-yomi periods of 30 seconds
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid time control: {s}");
+
+        let (base, rest) = s.split_once('+').ok_or_else(invalid)?;
+
+        // Negative, infinite and NaN times are rejected here
+        let seconds = |s: &str| {
+            s.parse()
+                .ok()
+                .and_then(|x| Duration::try_from_secs_f64(x).ok())
+                .ok_or_else(invalid)
+        };
+
+        let base = seconds(base)?.checked_mul(60).ok_or_else(invalid)?;
+
+        Ok(match rest.split_once('x') {
+            Some((period, periods)) => Self::Byoyomi {
+                base,
+                period: seconds(period)?,
+                periods: periods.parse().map_err(|_| invalid())?,
+            },
+            None => Self::Fischer {
+                base,
+                increment: seconds(rest)?,
+            },
+        })
+    }
+}
+
+// One side's clock, which runs while it is to move
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    control: TimeControl,
+    // Base time left
+    remaining: Duration,
+    // Byo-yomi periods left
+    periods: u32,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        match control {
+            TimeControl::Fischer { base, .. } => Self {
+                control,
+                remaining: base,
+                periods: 0,
+            },
+            TimeControl::Byoyomi { base, periods, .. } => Self {
+                control,
+                remaining: base,
+                periods,
+            },
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    // Time every move gets on top of the base time: the increment, or a
+    // period while any are left
+    pub fn per_move(&self) -> Duration {
+        match self.control {
+            TimeControl::Fischer { increment, .. } => increment,
+            TimeControl::Byoyomi { period, .. } if self.periods > 0 => period,
+            TimeControl::Byoyomi { .. } => Duration::ZERO,
+        }
+    }
+
+    // The longest the next move can take without losing on time
+    pub fn spendable(&self) -> Duration {
+        match self.control {
+            TimeControl::Fischer { .. } => self.remaining,
+            TimeControl::Byoyomi { period, .. } => {
+                self.remaining + period * self.periods
+            }
+        }
+    }
+
+    // Whether the clock has run out `elapsed` into a move
+    pub fn flagged(&self, elapsed: Duration) -> bool {
+        elapsed > self.spendable()
+    }
+
+    // Byo-yomi periods used up by a move that went `over` past the base time.
+    // A period is only used up by going past its end.
+    fn periods_used(over: Duration, period: Duration) -> u32 {
+        let used = over.as_nanos().saturating_sub(1) / period.as_nanos().max(1);
+
+        u32::try_from(used).unwrap_or(u32::MAX)
+    }
+
+    // Takes the time a move took off the clock. Returns false if it ran out.
+    pub fn stop(&mut self, elapsed: Duration) -> bool {
+        if self.flagged(elapsed) {
+            self.remaining = Duration::ZERO;
+            self.periods = 0;
+
+            return false;
+        }
+
+        let over = elapsed.saturating_sub(self.remaining);
+        self.remaining = self.remaining.saturating_sub(elapsed);
+
+        match self.control {
+            TimeControl::Fischer { increment, .. } => {
+                self.remaining += increment
+            }
+            TimeControl::Byoyomi { period, .. } => {
+                self.periods -= Self::periods_used(over, period)
+            }
+        }
+
+        true
+    }
+
+    // The time left `elapsed` into a move, like "4:59", with the byo-yomi
+    // periods left after it
+    pub fn display(&self, elapsed: Duration) -> String {
+        let mut left = self.remaining.saturating_sub(elapsed);
+        let mut periods = self.periods;
+
+        if let TimeControl::Byoyomi { period, .. } = self.control
+            && left.is_zero()
+            && periods > 0
+        {
+            let over = elapsed.saturating_sub(self.remaining);
+            let used = Self::periods_used(over, period);
+
+            periods = periods.saturating_sub(used);
+            left = match periods {
+                0 => Duration::ZERO,
+                _ => period * (used + 1) - over,
+            };
+        }
+
+        let seconds = left.as_secs();
+        let time = format!("{}:{:02}", seconds / 60, seconds % 60);
+
+        match self.control {
+            TimeControl::Fischer { .. } => time,
+            TimeControl::Byoyomi { .. } => format!("{time} ({periods})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NANO: Duration = Duration::from_nanos(1);
+
+    fn seconds(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn byoyomi() -> Clock {
+        Clock::new(TimeControl::Byoyomi {
+            base: seconds(10),
+            period: seconds(30),
+            periods: 3,
+        })
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!(
+            TimeControl::parse("5+3"),
+            Ok(TimeControl::Fischer { base, increment })
+                if base == seconds(300) && increment == seconds(3)
+        ));
+        assert!(matches!(
+            TimeControl::parse("0.5+0"),
+            Ok(TimeControl::Fischer { base, increment })
+                if base == seconds(30) && increment.is_zero()
+        ));
+        assert!(matches!(
+            TimeControl::parse("10+30x5"),
+            Ok(TimeControl::Byoyomi { base, period, periods: 5 })
+                if base == seconds(600) && period == seconds(30)
+        ));
+
+        for s in [
+            "", "5", "5+", "+3", "-1+3", "5+-3", "5+3x", "5+3x-1", "a+3",
+            "inf+3", "5+inf", "1e18+0",
+        ] {
+            assert!(TimeControl::parse(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn fischer_stop() {
+        let mut clock = Clock::new(TimeControl::parse("5+3").unwrap());
+
+        assert!(clock.stop(seconds(10)));
+        assert_eq!(clock.remaining(), seconds(293));
+        assert_eq!(clock.display(Duration::ZERO), "4:53");
+        assert_eq!(clock.display(seconds(293)), "0:00");
+
+        assert!(!clock.flagged(seconds(293)));
+        assert!(clock.flagged(seconds(293) + NANO));
+
+        assert!(!clock.stop(seconds(293) + NANO));
+        assert_eq!(clock.remaining(), Duration::ZERO);
+    }
+
+    #[test]
+    fn byoyomi_stop() {
+        let mut clock = byoyomi();
+
+        // Running out of base time uses no period
+        assert!(clock.stop(seconds(10)));
+        assert_eq!((clock.remaining(), clock.periods), (Duration::ZERO, 3));
+
+        // A move as long as a period keeps it
+        assert!(clock.stop(seconds(30)));
+        assert_eq!(clock.periods, 3);
+
+        assert!(clock.stop(seconds(30) + NANO));
+        assert_eq!(clock.periods, 2);
+
+        assert!(clock.stop(seconds(60)));
+        assert_eq!(clock.periods, 1);
+
+        assert!(!clock.flagged(seconds(30)));
+        assert!(clock.flagged(seconds(30) + NANO));
+
+        assert!(!clock.stop(seconds(30) + NANO));
+        assert_eq!(clock.periods, 0);
+    }
+
+    #[test]
+    fn byoyomi_display() {
+        let clock = byoyomi();
+
+        assert_eq!(clock.display(Duration::ZERO), "0:10 (3)");
+        assert_eq!(clock.display(seconds(10)), "0:30 (3)");
+        assert_eq!(clock.display(seconds(40)), "0:00 (3)");
+        assert_eq!(clock.display(seconds(40) + NANO), "0:29 (2)");
+        assert_eq!(clock.display(seconds(100)), "0:00 (1)");
+        assert_eq!(clock.display(seconds(100) + NANO), "0:00 (0)");
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    fmt::Display,
+    fs,
+    sync::{Arc, OnceLock},
+};
+
+use bitarray::BitArray;
+
+use crate::{
+    board::{BoardState, CORNERS, M, Piece, TOWERS, W, neighbours, to_linind},
+    bot::{PIECE_VALUE, Score},
+    nnue::{Network, NnueEvaluator},
+};
+
+const CORNER_COORDS: [[u16; 2]; 4] = [[0, 0], [0, 10], [10, 0], [10, 10]];
+
+// Static evaluation of a position, relative to white
+pub trait Evaluator: Send + Sync {
+    fn evaluate(&self, board: BoardState) -> Score;
+
+    // For evaluators that the search can update incrementally
+    fn network(&self) -> Option<&'static Network> {
+        None
+    }
+}
+
+// The original material count, with the king counted as a white piece
+pub struct Material;
+
+impl Evaluator for Material {
+    fn evaluate(&self, board: BoardState) -> Score {
+        (board.whites().count_ones() as Score
+            - board.blacks().count_ones() as Score)
+            * PIECE_VALUE
+    }
+}
+
+// Weight of each term of `TaflEvaluator`, in the same units as scores. Terms
+// are counted from white's point of view, so terms that are good for black
+// have negative weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalWeights {
+    pub white_piece: Score,
+    pub black_piece: Score,
+    // Per step of Manhattan distance to the closest corner
+    pub king_corner_distance: Score,
+    // Per corner the king can reach in one move
+    pub king_escape: Score,
+    // Per corner the king can reach in two moves but not in one
+    pub king_route: Score,
+    // Per black piece or empty hostile tower next to the king
+    pub king_attacker: Score,
+    // Per white piece next to the king
+    pub king_defender: Score,
+    // Per black piece on a square next to a corner
+    pub corner_guard: Score,
+    // Per legal move of white minus legal moves of black
+    pub mobility: Score,
+}
+
+// Weights set at startup with `--eval-weights`, replacing the built in ones
+static STARTUP_WEIGHTS: OnceLock<EvalWeights> = OnceLock::new();
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        Self {
+            white_piece: PIECE_VALUE,
+            black_piece: -PIECE_VALUE,
+            king_corner_distance: -10,
+            king_escape: 3 * PIECE_VALUE,
+            king_route: 40,
+            king_attacker: -60,
+            king_defender: 15,
+            corner_guard: -30,
+            mobility: 5,
+        }
+    }
+}
+
+impl EvalWeights {
+    pub const NAMES: [&str; 9] = [
+        "white_piece",
+        "black_piece",
+        "king_corner_distance",
+        "king_escape",
+        "king_route",
+        "king_attacker",
+        "king_defender",
+        "corner_guard",
+        "mobility",
+    ];
+
+    pub fn values_mut(&mut self) -> [&mut Score; 9] {
+        [
+            &mut self.white_piece,
+            &mut self.black_piece,
+            &mut self.king_corner_distance,
+            &mut self.king_escape,
+            &mut self.king_route,
+            &mut self.king_attacker,
+            &mut self.king_defender,
+            &mut self.corner_guard,
+            &mut self.mobility,
+        ]
+    }
+
+    // Parses lines like "mobility = 5", starting from the default weights.
+    // Blank lines and lines starting with '#' are skipped.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut weights = Self::default();
+
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key = value: {line}"))?;
+
+            let (key, value) = (key.trim(), value.trim());
+
+            let i = Self::NAMES
+                .iter()
+                .position(|&name| name == key)
+                .ok_or_else(|| format!("Unknown eval weight: {key}"))?;
+
+            *weights.values_mut()[i] = value
+                .parse()
+                .map_err(|_| format!("Invalid value for {key}: {value}"))?;
+        }
+
+        Ok(weights)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let s = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {path}: {e}"))?;
+
+        Self::parse(&s)
+    }
+
+    // Makes these the weights of every tafl evaluator created from now on
+    pub fn set_startup(self) {
+        STARTUP_WEIGHTS.set(self).expect("Eval weights already set");
+    }
+
+    pub fn startup() -> Self {
+        STARTUP_WEIGHTS.get().copied().unwrap_or_default()
+    }
+}
+
+impl Display for EvalWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut weights = *self;
+
+        for (name, value) in Self::NAMES.iter().zip(weights.values_mut()) {
+            writeln!(f, "{name} = {value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// The value of each term for a position, before weighting
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvalTerms {
+    pub white_piece: Score,
+    pub black_piece: Score,
+    pub king_corner_distance: Score,
+    pub king_escape: Score,
+    pub king_route: Score,
+    pub king_attacker: Score,
+    pub king_defender: Score,
+    pub corner_guard: Score,
+    pub mobility: Score,
+}
+
+impl EvalTerms {
+    // In the order of `EvalWeights::NAMES`
+    pub fn values(&self) -> [Score; 9] {
+        [
+            self.white_piece,
+            self.black_piece,
+            self.king_corner_distance,
+            self.king_escape,
+            self.king_route,
+            self.king_attacker,
+            self.king_defender,
+            self.corner_guard,
+            self.mobility,
+        ]
+    }
+
+    pub fn of(board: BoardState) -> Self {
+        let king = board.king();
+        let blacks = board.blacks();
+        let white_pieces = board.whites() & !king;
+
+        let mobility = |pieces: BitArray<M>| {
+            pieces
+                .trues_iter()
+                .map(|i| {
+                    board
+                        .moves_from([(i / W) as u16, (i % W) as u16])
+                        .count_ones() as Score
+                })
+                .sum::<Score>()
+        };
+
+        let corner_squares = neighbours(CORNERS);
+
+        let mut terms = Self {
+            white_piece: white_pieces.count_ones() as Score,
+            black_piece: blacks.count_ones() as Score,
+            corner_guard: (blacks & corner_squares).count_ones() as Score,
+            mobility: mobility(board.whites()) - mobility(blacks),
+            ..Default::default()
+        };
+
+        let Some([y, x]) = board.king_coord() else {
+            return terms;
+        };
+
+        let around_king = neighbours(king);
+        let empty_towers = TOWERS & !board.whites() & !blacks;
+
+        terms.king_corner_distance = CORNER_COORDS
+            .iter()
+            .map(|&[cy, cx]| (y.abs_diff(cy) + x.abs_diff(cx)) as Score)
+            .min()
+            .unwrap();
+
+        let escapes = board.king_escapes();
+
+        terms.king_escape = escapes.count_ones() as Score;
+
+        terms.king_attacker =
+            (around_king & (blacks | empty_towers)).count_ones() as Score;
+
+        terms.king_defender =
+            (around_king & white_pieces).count_ones() as Score;
+
+        // Corners reachable by first moving the king to any square it can
+        // reach now, leaving out those it can reach right away
+        let mut without_king = board;
+        without_king.set(to_linind([y, x]).unwrap(), Piece::Empty);
+
+        let mut routes = BitArray::<M>::new();
+
+        for i in board.moves_from([y, x]).trues_iter() {
+            let coord = [(i / W) as u16, (i % W) as u16];
+
+            let mut moved = without_king;
+            moved.set(i, Piece::King);
+
+            routes |= moved.moves_from(coord) & CORNERS;
+        }
+
+        terms.king_route = (routes & !escapes).count_ones() as Score;
+
+        terms
+    }
+
+    pub fn weighted(&self, weights: &EvalWeights) -> Score {
+        self.white_piece * weights.white_piece
+            + self.black_piece * weights.black_piece
+            + self.king_corner_distance * weights.king_corner_distance
+            + self.king_escape * weights.king_escape
+            + self.king_route * weights.king_route
+            + self.king_attacker * weights.king_attacker
+            + self.king_defender * weights.king_defender
+            + self.corner_guard * weights.corner_guard
+            + self.mobility * weights.mobility
+    }
+}
+
+// Evaluation built from tafl specific terms: material, the king's way to the
+// corners and the pieces around it, black's hold on the corners, and
+// mobility
+pub struct TaflEvaluator {
+    pub weights: EvalWeights,
+}
+
+impl Evaluator for TaflEvaluator {
+    fn evaluate(&self, board: BoardState) -> Score {
+        EvalTerms::of(board).weighted(&self.weights)
+    }
+}
+
+// Which evaluator a searcher uses, selected with "eval=material|tafl|nnue".
+// Tafl weights can be loaded from a file with "weights=<path>", and the
+// network for nnue comes from `--nnue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalKind {
+    Material,
+    Tafl(EvalWeights),
+    Nnue,
+}
+
+impl EvalKind {
+    pub fn evaluator(self) -> Arc<dyn Evaluator> {
+        match self {
+            Self::Material => Arc::new(Material),
+            Self::Tafl(weights) => Arc::new(TaflEvaluator { weights }),
+            Self::Nnue => Arc::new(NnueEvaluator {
+                network: Network::startup(),
+            }),
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::{
+    fs,
     io::{Stdout, stdout},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use ahash::RandomState;
 use bitarray::BitArray;
 use crossterm::{
     cursor,
@@ -16,13 +16,34 @@ use crossterm::{
     style::Stylize,
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use hashbrown::HashMap;
 
-use crate::board::{
-    self, BoardState, Faction, HighlightedBoardState, to_linind,
-    to_readable_coord,
+use crate::{
+    board::{
+        self, BoardState, Faction, HighlightedBoardState, from_readable_coord,
+        to_linind, to_readable_coord, to_readable_move,
+    },
+    bot::{
+        Bot, BotConfig, Engine, Move, SearchResult, Searcher, describe_score,
+        format_pv,
+    },
+    clock::{Clock, TimeControl},
+    game_tree::{GameEnd, GameTree},
+    mcts::Mcts,
+    ponder::Ponder,
+    selfplay::{MAX_GAME_PLIES, Outcome},
 };
 
+// Where 'x' saves the game tree
+const EXPORT_PATH: &str = "variations.txt";
+
+// The move list is drawn right of the board, with this many lines between
+// its borders. The clocks go under it, right of the status bar.
+const MOVE_LIST_COLUMN: u16 = 52;
+const MOVE_LIST_ROWS: u16 = 24;
+
+// How often the clocks are redrawn while waiting for input
+const CLOCK_REFRESH: Duration = Duration::from_millis(100);
+
 fn screen_coord_to_game_coord([y, x]: [u16; 2]) -> Option<[u16; 2]> {
     let row = y.checked_sub(4)? / 2;
     if y > 24 || y % 2 != 0 {
@@ -37,15 +58,81 @@ fn screen_coord_to_game_coord([y, x]: [u16; 2]) -> Option<[u16; 2]> {
     Some([row, col])
 }
 
+fn pv_squares(pv: &[Move]) -> BitArray<{ board::M }> {
+    let mut squares = BitArray::new();
+
+    for coord in pv.iter().flatten() {
+        squares.set(to_linind(*coord).unwrap(), true);
+    }
+
+    squares
+}
+
+// Lists the candidate moves of a multi-PV search, best first
+fn print_analysis(results: &[SearchResult]) {
+    println!("┏━━━━┯━━━━━━━━━┯━━━━━━━━━━━━━━━━━━━━━┓");
+
+    for (i, result) in results.iter().enumerate() {
+        println!(
+            "┃ {:>2} │ {:<7} │ {:<19} ┃ {}",
+            i + 1,
+            to_readable_move(result.best_move()),
+            describe_score(result.score),
+            format_pv(&result.pv[1..]).dim(),
+        );
+    }
+
+    println!("┗━━━━┷━━━━━━━━━┷━━━━━━━━━━━━━━━━━━━━━┛");
+}
+
+// A bot playing one side of the game
+struct BotPlayer {
+    config: BotConfig,
+    bot: Bot,
+}
+
+impl BotPlayer {
+    fn new(config: BotConfig) -> Self {
+        Self {
+            config,
+            bot: Bot::new(&config),
+        }
+    }
+}
+
 pub struct GameState {
     out: Stdout,
     board: BoardState,
     selected: Option<[u16; 2]>,
     legal_moves: BitArray<{ board::M }>,
+    // Square picked by the keyboard, shown once it has been moved
+    cursor: Option<[u16; 2]>,
+    // A move being typed in, like "D1 D4"
+    typed_move: Option<String>,
     turn: Faction,
-    history: Vec<BoardState>,
+    // Every move played, with the variations tried along the way
+    tree: GameTree,
     looking_back_at: Option<usize>,
-    trans_table: HashMap<BoardState, (u32, f64), RandomState>,
+    // Mark the pieces the last move captured the next time the board is
+    // drawn
+    show_captures: bool,
+    searcher: Searcher,
+    mcts: Mcts,
+    engine: Engine,
+    pv: Vec<Move>,
+    show_pv: bool,
+    analysis_lines: usize,
+    // Search the position in the background while waiting for input
+    pondering: bool,
+    ponder: Option<Ponder>,
+    // The bot playing each faction, if any
+    bots: [Option<BotPlayer>; 2],
+    // Pause after each move in a game between two bots
+    move_delay: Duration,
+    // Each faction's clock when playing with a time control, and when the
+    // faction to move started on its move
+    clocks: Option<[Clock; 2]>,
+    turn_start: Instant,
 }
 
 impl GameState {
@@ -57,18 +144,145 @@ impl GameState {
             board,
             selected: None,
             legal_moves: BitArray::new(),
+            cursor: None,
+            typed_move: None,
             turn: Faction::Black,
-            history: Vec::new(),
+            tree: GameTree::new(board, None),
             looking_back_at: None,
-            trans_table: HashMap::default(),
+            show_captures: false,
+            searcher: Searcher::default(),
+            mcts: Mcts::default(),
+            engine: Engine::AlphaBeta,
+            pv: Vec::new(),
+            show_pv: false,
+            analysis_lines: 1,
+            pondering: true,
+            ponder: None,
+            bots: [None, None],
+            move_delay: Duration::ZERO,
+            clocks: None,
+            turn_start: Instant::now(),
+        }
+    }
+
+    // A game where the bot plays `faction` with `config`, moving as soon as
+    // it is its turn
+    pub fn against_bot(faction: Faction, config: BotConfig) -> Self {
+        let mut game = Self::new();
+        game.bots[faction as usize] = Some(BotPlayer::new(config));
+
+        game
+    }
+
+    // A game between two bots, indexed by faction, pausing for `move_delay`
+    // after each move so it can be followed
+    pub fn bot_vs_bot(configs: [BotConfig; 2], move_delay: Duration) -> Self {
+        Self {
+            bots: configs.map(|config| Some(BotPlayer::new(config))),
+            move_delay,
+            ..Self::new()
         }
     }
 
+    // Gives both factions a clock with `control`, losing the game when it
+    // runs out
+    pub fn with_time_control(self, control: TimeControl) -> Self {
+        let clocks = Some([Clock::new(control); 2]);
+
+        Self {
+            clocks,
+            tree: GameTree::new(self.board, clocks),
+            ..self
+        }
+    }
+
+    fn has_bots(&self) -> bool {
+        self.bots.iter().any(Option::is_some)
+    }
+
+    // The searcher whose transposition table pondering warms up: the bot's
+    // when playing against it
+    fn ponder_searcher(&self) -> Option<&Searcher> {
+        if !self.has_bots() {
+            return (self.engine == Engine::AlphaBeta)
+                .then_some(&self.searcher);
+        }
+
+        match &self.bots[self.turn.other_faction() as usize] {
+            Some(BotPlayer {
+                bot: Bot::AlphaBeta(searcher),
+                ..
+            }) => Some(searcher),
+            _ => None,
+        }
+    }
+
+    // Against the bot, the position after the reply it expects, so it can
+    // answer at once if the human plays it. Otherwise the current position.
+    fn ponder_position(&self) -> (BoardState, Faction) {
+        let expected =
+            self.pv.first().filter(|_| self.has_bots()).filter(|&&mv| {
+                self.board.all_moves(self.turn).any(|x| x == mv)
+            });
+
+        if let Some(&[from, to]) = expected {
+            let mut board = self.board;
+
+            if !board.do_move(from, to) {
+                return (board, self.turn.other_faction());
+            }
+        }
+
+        (self.board, self.turn)
+    }
+
+    // Keeps the ponder search on the current position, or stops it when
+    // pondering is off
+    fn update_ponder(&mut self) {
+        let (board, turn) = self.ponder_position();
+
+        let wanted = self.pondering
+            && self.ponder_searcher().is_some()
+            && board.all_moves(turn).next().is_some();
+
+        if let Some(ponder) = self
+            .ponder
+            .take_if(|ponder| !wanted || !ponder.is_for(board, turn))
+        {
+            ponder.stop();
+        }
+
+        if wanted && self.ponder.is_none() {
+            self.ponder = Some(Ponder::start(
+                self.ponder_searcher().unwrap(),
+                board,
+                turn,
+            ));
+        }
+    }
+
+    // Stops pondering, returning the result and how long it searched if it
+    // was on the current position
+    fn take_ponder_result(&mut self) -> Option<(SearchResult, Duration)> {
+        let ponder = self.ponder.take()?;
+
+        let hit = ponder.is_for(self.board, self.turn);
+        let result = ponder.stop();
+
+        hit.then_some(result)
+    }
+
     fn render(&mut self) {
         execute!(self.out, cursor::MoveTo(0, 0)).unwrap();
 
         if let Some(i) = self.looking_back_at {
-            print!("{}", self.history[i]);
+            print!("{}", HighlightedBoardState {
+                board: self.tree.board_at(i),
+                highlights: BitArray::new(),
+                last_move: self.tree.move_at(i),
+                captured: BitArray::new(),
+                cursor: self.cursor,
+            });
 
             let turn = [Faction::Black, Faction::White][i % 2];
 
@@ -90,7 +304,25 @@ impl GameState {
                 }
             );
         } else {
-            print!("{}", self.board);
+            let highlights = match self.show_pv {
+                true => self.legal_moves | pv_squares(&self.pv),
+                false => self.legal_moves,
+            };
+
+            let depth = self.tree.depth();
+
+            print!("{}", HighlightedBoardState {
+                board: self.board,
+                highlights,
+                last_move: self.tree.move_at(depth),
+                captured: match self.show_captures {
+                    true => self.tree.captures_at(depth),
+                    false => BitArray::new(),
+                },
+                cursor: self.cursor,
+            });
+
+            self.show_captures = false;
 
             println!(
                 "\
@@ -103,8 +335,8 @@ impl GameState {
                 }
                 .bold(),
                 self.turn,
-                self.history.len(),
-                if self.history.is_empty() {
+                self.tree.depth(),
+                if self.tree.depth() == 0 {
                     format!("{}", "◀".dim())
                 } else {
                     "◀".to_owned()
@@ -112,6 +344,263 @@ impl GameState {
                 "▶".dim(),
             );
         }
+
+        self.render_move_list();
+        self.render_clocks();
+    }
+
+    // Draws the time each faction has left under the move list, the one
+    // running in bold
+    fn render_clocks(&mut self) {
+        let Some(clocks) = self.clocks else {
+            return;
+        };
+
+        let [black, white] = [Faction::Black, Faction::White].map(|faction| {
+            let elapsed = match faction == self.turn {
+                true => self.turn_start.elapsed(),
+                false => Duration::ZERO,
+            };
+
+            clocks[faction as usize].display(elapsed)
+        });
+
+        // As wide as the move list, unless a clock needs more room
+        let width = black.chars().count().max(white.chars().count()).max(8);
+
+        let [black, white] = [(black, Faction::Black), (white, Faction::White)]
+            .map(|(text, faction)| {
+                let text = format!("{text:>width$}");
+
+                match faction == self.turn {
+                    true => text.bold(),
+                    false => text.reset(),
+                }
+            });
+
+        let rows = [
+            format!("┏{}┯{}┓", "━".repeat(width + 4), "━".repeat(width + 5)),
+            format!("┃ {} {black} │ {} {white}  ┃", "◯".bold(), "⬤".bold()),
+            format!("┗{}┷{}┛", "━".repeat(width + 4), "━".repeat(width + 5)),
+        ];
+
+        execute!(self.out, cursor::SavePosition).unwrap();
+
+        for (row, text) in (MOVE_LIST_ROWS + 2..).zip(rows) {
+            execute!(
+                self.out,
+                cursor::MoveTo(MOVE_LIST_COLUMN, row),
+                terminal::Clear(terminal::ClearType::UntilNewLine)
+            )
+            .unwrap();
+            print!("{text}");
+        }
+
+        execute!(self.out, cursor::RestorePosition).unwrap();
+    }
+
+    // Waits for input, keeping the clocks up to date meanwhile. Fails with
+    // "break" once the faction to move has run out of time.
+    fn next_event(&mut self) -> Result<Event, &'static str> {
+        if self.clocks.is_none() {
+            return event::read().map_err(|_| "break");
+        }
+
+        while !event::poll(CLOCK_REFRESH).unwrap() {
+            if let Some(clocks) = &mut self.clocks
+                && clocks[self.turn as usize].flagged(self.turn_start.elapsed())
+            {
+                clocks[self.turn as usize].stop(self.turn_start.elapsed());
+
+                return self.lose_on_time();
+            }
+
+            self.render_clocks();
+        }
+
+        event::read().map_err(|_| "break")
+    }
+
+    // Ends the game for the faction to move, which has run out of time
+    fn lose_on_time<T>(&mut self) -> Result<T, &'static str> {
+        self.turn_start = Instant::now();
+        self.render();
+        let reason = format!("{:?} ran out of time", self.turn);
+        self.show_result(Some(self.turn.other_faction()), Some(&reason));
+
+        Err("break")
+    }
+
+    // Ends the game for the faction to move, which has no legal moves
+    fn lose_without_moves<T>(&mut self) -> Result<T, &'static str> {
+        let reason = format!("{:?} has no moves", self.turn);
+        self.show_result(Some(self.turn.other_faction()), Some(&reason));
+
+        Err("break")
+    }
+
+    // Records the result in the game tree and the move list, replaces the
+    // status bar with the winner, or a draw, then waits for a key. A reason
+    // for the result takes the place of the hint to quit. 'x' still saves
+    // the game tree before quitting.
+    fn show_result(&mut self, winner: Option<Faction>, reason: Option<&str>) {
+        self.tree.set_end(GameEnd {
+            outcome: winner.map_or(Outcome::Draw, Outcome::Win),
+            reason: reason.map(str::to_owned),
+        });
+        self.render_move_list();
+
+        execute!(
+            self.out,
+            cursor::MoveUp(3),
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+
+        let (symbol, result) = match winner {
+            Some(Faction::Black) => ("◯", "Black wins!"),
+            Some(Faction::White) => ("⬤", "White wins!"),
+            None => ("½", "   Draw!   "),
+        };
+
+        let text = reason.unwrap_or("Press any key to quit");
+        let bar = "━".repeat(text.chars().count() + 2);
+
+        println!(
+            "\
+┏━━━┓ ┏━━━━━━━━━━━━━┓ ┏{bar}┓
+┃ {} ┃ ┃ {result} ┃ ┃ {text} ┃
+┗━━━┛ ┗━━━━━━━━━━━━━┛ ┗{bar}┛",
+            symbol.bold(),
+        );
+
+        self.render_clocks();
+
+        while let Ok(x) = event::read() {
+            match x {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('x'),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => self.export(),
+                Event::Key(_) => break,
+                _ => {}
+            }
+        }
+    }
+
+    // The result of the game, and why it ended, for the end of the move
+    // list
+    fn result_lines(&self) -> Vec<String> {
+        let Some(end) = self.tree.end() else {
+            return Vec::new();
+        };
+
+        let result = match end.outcome {
+            Outcome::Win(winner) => format!("{winner:?} wins"),
+            Outcome::Draw => "Draw".to_owned(),
+            Outcome::Unfinished => unreachable!(),
+        };
+
+        [result].into_iter().chain(end.reason.clone()).collect()
+    }
+
+    // The first move list line shown, scrolled so the position on the board
+    // and, at the end of the game, the result are in view
+    fn move_list_start(&self) -> usize {
+        let depth = self.tree.depth();
+        let lines = depth + self.result_lines().len();
+
+        let last_shown = match self.looking_back_at {
+            Some(i) => i.saturating_sub(1),
+            None => lines.saturating_sub(1),
+        };
+
+        last_shown.saturating_sub(MOVE_LIST_ROWS as usize - 1)
+    }
+
+    // Lists the moves leading to the current position, with what they
+    // captured, beside the board. The move leading to the position on the
+    // board is highlighted.
+    fn render_move_list(&mut self) {
+        let depth = self.tree.depth();
+        let viewed = self.looking_back_at.unwrap_or(depth);
+        let start = self.move_list_start();
+        let result = self.result_lines();
+
+        execute!(
+            self.out,
+            cursor::SavePosition,
+            cursor::MoveTo(MOVE_LIST_COLUMN, 0)
+        )
+        .unwrap();
+        print!("┏{}┓", "━".repeat(26));
+
+        for row in 1..=MOVE_LIST_ROWS {
+            let k = start + row as usize;
+
+            let line = if k <= depth {
+                let [from, to] = self.tree.move_at(k).unwrap();
+                let captured = self.tree.captures_at(k);
+
+                let captures: Vec<_> = (0..11)
+                    .flat_map(|y| (0..11).map(move |x| [y, x]))
+                    .filter(|&coord| captured[to_linind(coord).unwrap()])
+                    .map(to_readable_coord)
+                    .collect();
+                let squares = captures.join(",");
+
+                // The move takes 15 of the 24 columns. Captures that don't
+                // fit in the rest are only counted.
+                format!(
+                    "{k:3}. {} {:<7} {}",
+                    if k % 2 == 1 { "◯" } else { "⬤" },
+                    to_readable_move([from, to]),
+                    match squares.len() {
+                        0 => String::new(),
+                        1..=8 => format!("x{squares}"),
+                        _ => format!("x{}", captures.len()),
+                    },
+                )
+            } else if let Some(line) = result.get(k - depth - 1) {
+                line.clone()
+            } else {
+                String::new()
+            };
+
+            execute!(self.out, cursor::MoveTo(MOVE_LIST_COLUMN, row)).unwrap();
+
+            let line = format!(" {line:<24} ");
+
+            if k == viewed {
+                print!("┃{}┃", line.reverse());
+            } else {
+                print!("┃{line}┃");
+            }
+        }
+
+        execute!(
+            self.out,
+            cursor::MoveTo(MOVE_LIST_COLUMN, MOVE_LIST_ROWS + 1)
+        )
+        .unwrap();
+        print!("┗{}┛", "━".repeat(26));
+
+        execute!(self.out, cursor::RestorePosition).unwrap();
+    }
+
+    // Shows the position after the move on move list line `row`
+    fn click_move_list(&mut self, row: u16) {
+        let k = self.move_list_start() + row as usize;
+        let depth = self.tree.depth();
+
+        if k > depth {
+            return;
+        }
+
+        self.looking_back_at = (k < depth).then_some(k);
+
+        self.render();
     }
 
     fn handle_mouse_input(
@@ -119,14 +608,26 @@ impl GameState {
         column: u16,
         row: u16,
     ) -> Result<(), &'static str> {
-        if self.looking_back_at.is_some() {
-            return Ok(());
-        }
-
         let Some(coord) = screen_coord_to_game_coord([row, column]) else {
             return Ok(());
         };
 
+        self.select_square(coord)
+    }
+
+    // Selects the piece on `coord`, or moves the selected piece there, the
+    // same for clicks and the keyboard
+    fn select_square(&mut self, coord: [u16; 2]) -> Result<(), &'static str> {
+        // Picking a square while looking back resumes play from there
+        if let Some(i) = self.looking_back_at {
+            self.resume_from(i);
+            self.render();
+
+            if self.bots[self.turn as usize].is_some() {
+                return self.bot_move();
+            }
+        }
+
         if self.board.get_2d(coord).and_then(|x| x.try_into().ok())
             == Some(self.turn)
         {
@@ -134,58 +635,387 @@ impl GameState {
 
             self.legal_moves = self.board.moves_from(coord);
 
-            execute!(self.out, cursor::MoveTo(0, 0)).unwrap();
-            println!("{}", HighlightedBoardState(self.board, self.legal_moves));
+            self.render();
         } else if self.legal_moves[to_linind(coord).unwrap()] {
-            self.history.push(self.board);
+            self.play_move(self.selected.unwrap(), coord)?;
+            self.bot_move()?;
+        }
+
+        Ok(())
+    }
+
+    // Moves the keyboard cursor, showing it in the middle of the board the
+    // first time
+    fn move_cursor(&mut self, [dy, dx]: [i16; 2]) {
+        self.cursor = Some(match self.cursor {
+            Some([y, x]) => [
+                y.saturating_add_signed(dy).min(10),
+                x.saturating_add_signed(dx).min(10),
+            ],
+            None => [5, 5],
+        });
 
-            let won = self.board.do_move(self.selected.unwrap(), coord);
+        self.render();
+    }
 
+    // Cancels whatever the keyboard was doing: the selection first, then the
+    // cursor
+    fn cancel(&mut self) {
+        if self.selected.is_some() {
             self.selected = None;
             self.legal_moves = BitArray::new();
+        } else {
+            self.cursor = None;
+        }
 
-            self.turn = self.turn.other_faction();
+        self.render();
+    }
 
-            self.render();
+    // Shows the move being typed under the board
+    fn show_typed_move(&mut self) {
+        self.render();
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
 
-            if won {
-                execute!(
-                    self.out,
-                    cursor::MoveUp(3),
-                    terminal::Clear(terminal::ClearType::FromCursorDown)
-                )
-                .unwrap();
-
-                let winning_faction = self.turn.other_faction();
-
-                println!(
-                    "\
-┏━━━┓ ┏━━━━━━━━━━━━━┓ ┏━━━━━━━━━━━━━━━━━━━━━━━┓
-┃ {} ┃ ┃ {:?} wins! ┃ ┃ Press any key to quit ┃
-┗━━━┛ ┗━━━━━━━━━━━━━┛ ┗━━━━━━━━━━━━━━━━━━━━━━━┛",
-                    match winning_faction {
-                        Faction::Black => "◯",
-                        Faction::White => "⬤",
-                    }
-                    .bold(),
-                    winning_faction,
-                );
+        if let Some(text) = &self.typed_move {
+            println!("Move: {text}_");
+        }
+    }
 
-                while let Ok(x) = event::read() {
-                    if let Event::Key(_) = x {
-                        break;
-                    }
-                }
+    // Handles a key while a move is being typed. Enter plays it, Esc drops
+    // it.
+    fn type_key(&mut self, code: KeyCode) -> Result<(), &'static str> {
+        let text = self.typed_move.as_mut().unwrap();
+
+        match code {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => self.typed_move = None,
+            KeyCode::Enter => {
+                let text = self.typed_move.take().unwrap();
+
+                return self.play_typed_move(&text);
+            }
+            _ => {}
+        }
+
+        self.show_typed_move();
+
+        Ok(())
+    }
+
+    // Plays a move written as two squares, like "D1 D4" or "D1-D4"
+    fn play_typed_move(&mut self, text: &str) -> Result<(), &'static str> {
+        let squares: Option<Vec<_>> = text
+            .split(|c: char| c.is_whitespace() || c == '-')
+            .filter(|x| !x.is_empty())
+            .map(from_readable_coord)
+            .collect();
+
+        let Some(&[from, to]) = squares.as_deref() else {
+            self.show_typed_move();
+            println!("Not a move: {text}");
+
+            return Ok(());
+        };
+
+        self.selected = None;
+        self.legal_moves = BitArray::new();
+        self.select_square(from)?;
+
+        if self.selected == Some(from)
+            && self.legal_moves[to_linind(to).unwrap()]
+        {
+            return self.select_square(to);
+        }
+
+        self.selected = None;
+        self.legal_moves = BitArray::new();
+        self.show_typed_move();
+        println!("Illegal move: {text}");
+
+        Ok(())
+    }
+
+    // Takes up the position and clocks of the current node of the tree after
+    // moving through it. The clock to move starts running from now.
+    fn load_tree_node(&mut self) {
+        self.board = self.tree.board();
+        self.clocks = self.tree.clocks();
+        self.turn_start = Instant::now();
+    }
+
+    // Takes back the last move, returning whether there was one
+    fn undo(&mut self) -> bool {
+        if !self.tree.back() {
+            return false;
+        }
+
+        self.load_tree_node();
+        self.turn = self.turn.other_faction();
+
+        true
+    }
+
+    // Plays the move taken back last from here again
+    fn redo(&mut self) -> bool {
+        if !self.tree.forward() {
+            return false;
+        }
+
+        self.load_tree_node();
+        self.turn = self.turn.other_faction();
+
+        true
+    }
+
+    // Goes back to the position before move `i`. The later moves stay in the
+    // tree, and a different move from there starts a variation.
+    fn resume_from(&mut self, i: usize) {
+        while self.tree.depth() > i {
+            self.undo();
+        }
+
+        self.looking_back_at = None;
+        self.selected = None;
+        self.legal_moves = BitArray::new();
+        self.pv.clear();
+    }
+
+    // Undoes or redoes moves until it is a human's turn again, so a takeback
+    // against the bot also takes back its reply. A bot left to move with
+    // nothing to redo plays instead.
+    fn step_history(&mut self, forward: bool) -> Result<(), &'static str> {
+        self.resume_from(self.tree.depth());
+
+        loop {
+            let moved = match forward {
+                false => self.undo(),
+                true => self.redo(),
+            };
+
+            if !moved || self.bots[self.turn as usize].is_none() {
+                break;
+            }
+        }
+
+        self.render();
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+
+        self.bot_move()
+    }
+
+    // Switches the last move for another variation tried at that point
+    fn switch_variation(&mut self, offset: isize) -> Result<(), &'static str> {
+        self.resume_from(self.tree.depth());
+        self.tree.switch_variation(offset);
+        self.load_tree_node();
+
+        self.render();
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+
+        let (i, count) = self.tree.variation();
+        println!("Variation {} of {count}", i + 1);
+
+        self.bot_move()
+    }
+
+    // Writes every line of the game tree to EXPORT_PATH as game records
+    fn export(&mut self) {
+        let records = self.tree.records();
+
+        let text: String =
+            records.iter().map(|record| format!("{record}\n")).collect();
+
+        self.render();
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+
+        match fs::write(EXPORT_PATH, text) {
+            Ok(()) => {
+                println!("Saved {} lines to {EXPORT_PATH}", records.len())
+            }
+            Err(e) => println!("Could not write {EXPORT_PATH}: {e}"),
+        }
+    }
+
+    // Makes a move for the faction to move. Fails with "break" once the
+    // winner has been shown.
+    fn play_move(
+        &mut self,
+        from: [u16; 2],
+        to: [u16; 2],
+    ) -> Result<(), &'static str> {
+        if let Some(clocks) = &mut self.clocks
+            && !clocks[self.turn as usize].stop(self.turn_start.elapsed())
+        {
+            return self.lose_on_time();
+        }
+
+        self.turn_start = Instant::now();
+
+        let won = self.board.do_move(from, to);
+
+        self.tree.play([from, to], self.board, self.clocks);
+
+        self.selected = None;
+        self.legal_moves = BitArray::new();
+        self.pv.clear();
+
+        self.turn = self.turn.other_faction();
+
+        self.show_captures = true;
+        self.render();
+
+        if won {
+            self.show_result(Some(self.turn.other_faction()), None);
+
+            return Err("break");
+        }
+
+        if self.board.all_moves(self.turn).next().is_none() {
+            return self.lose_without_moves();
+        }
+
+        println!();
+
+        Ok(())
+    }
+
+    // Lets the bots move for as long as it is their turn, which is the rest
+    // of the game when both sides are bots
+    fn bot_move(&mut self) -> Result<(), &'static str> {
+        loop {
+            let Some(config) = self.bots[self.turn as usize]
+                .as_ref()
+                .map(|player| player.config)
+            else {
+                return Ok(());
+            };
+
+            if self.board.all_moves(self.turn).next().is_none() {
+                self.render();
+
+                return self.lose_without_moves();
+            }
+
+            // Bots playing each other could shuffle pieces forever
+            if self.bots.iter().all(Option::is_some)
+                && self.tree.depth() >= MAX_GAME_PLIES
+            {
+                self.render();
+
+                let reason = format!("No win in {MAX_GAME_PLIES} moves");
+                self.show_result(None, Some(&reason));
 
                 return Err("break");
             }
 
-            println!();
+            self.play_bot_move(config)?;
+
+            if self.bots[self.turn as usize].is_some() {
+                self.wait_between_moves()?;
+            }
+        }
+    }
+
+    // Waits for `move_delay` before the next bot move. Fails with "break" if
+    // the user quits with q meanwhile.
+    fn wait_between_moves(&mut self) -> Result<(), &'static str> {
+        let deadline = Instant::now() + self.move_delay;
+
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            if event::poll(left).unwrap()
+                && let Event::Key(KeyEvent {
+                    code: KeyCode::Char('q'),
+                    ..
+                }) = event::read().unwrap()
+            {
+                return Err("break");
+            }
         }
 
         Ok(())
     }
 
+    fn play_bot_move(&mut self, config: BotConfig) -> Result<(), &'static str> {
+        let faction = self.turn;
+
+        // The pause between bots' moves is not on their clocks
+        self.turn_start = Instant::now();
+
+        let config = match &self.clocks {
+            Some(clocks) => config.with_clock(&clocks[faction as usize]),
+            None => config,
+        };
+
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+        println!("{:?} is thinking...", faction);
+
+        // Against an alpha-beta bot, the human may have played the expected
+        // reply. A timed bot searches as deep as its time allows, so pondering
+        // for that long is as good.
+        let result = match self.take_ponder_result().filter(|(result, time)| {
+            result.depth >= config.depth
+                || config.time.is_some_and(|budget| *time >= budget)
+        }) {
+            Some((result, _)) => result,
+            None => match self.bots[faction as usize]
+                .as_mut()
+                .unwrap()
+                .bot
+                .think(&config, self.board, self.turn)
+            {
+                Ok(result) => result,
+                Err(_) => return self.lose_without_moves(),
+            },
+        };
+
+        let [from, to] = result.best_move();
+
+        self.play_move(from, to)?;
+        self.pv = result.pv[1..].to_vec();
+
+        execute!(
+            self.out,
+            terminal::Clear(terminal::ClearType::FromCursorDown)
+        )
+        .unwrap();
+        println!(
+            "{:?} played {}, {}, {}",
+            faction,
+            to_readable_move([from, to]),
+            describe_score(result.score),
+            match config.engine {
+                _ if result.nodes == 0 => "from the book".to_owned(),
+                Engine::AlphaBeta => format!("at depth: {}", result.depth),
+                Engine::Mcts => format!("with {} playouts", result.nodes),
+            },
+        );
+
+        Ok(())
+    }
+
     pub fn run(&mut self) {
         execute!(
             self.out,
@@ -197,10 +1027,92 @@ impl GameState {
         )
         .unwrap();
 
+        self.turn_start = Instant::now();
         self.render();
 
-        while let Ok(x) = event::read() {
+        // The bot opens the game when it plays black
+        if self.bot_move().is_err() {
+            self.exit();
+            return;
+        }
+
+        self.update_ponder();
+
+        while let Ok(x) = self.next_event() {
             match x {
+                // Typing a move takes every key until Enter or Esc
+                Event::Key(KeyEvent {
+                    code,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) if self.typed_move.is_some() => match self.type_key(code) {
+                    Ok(()) => {}
+                    Err("break") => break,
+                    Err(x) => panic!("{x}"),
+                },
+
+                // A column letter starts typing a move
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c @ 'A'..='K'),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.typed_move = Some(c.to_string());
+                    self.show_typed_move();
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c @ ('h' | 'j' | 'k' | 'l')),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => self.move_cursor(match c {
+                    'h' => [0, -1],
+                    'j' => [1, 0],
+                    'k' => [-1, 0],
+                    _ => [0, 1],
+                }),
+
+                // The arrow keys move the cursor once it is shown, and browse
+                // the history otherwise
+                Event::Key(KeyEvent {
+                    code:
+                        code @ (KeyCode::Left
+                        | KeyCode::Right
+                        | KeyCode::Up
+                        | KeyCode::Down),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) if self.cursor.is_some() => self.move_cursor(match code {
+                    KeyCode::Left => [0, -1],
+                    KeyCode::Down => [1, 0],
+                    KeyCode::Up => [-1, 0],
+                    _ => [0, 1],
+                }),
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Enter,
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => {
+                    if let Some(coord) = self.cursor {
+                        match self.select_square(coord) {
+                            Ok(()) => {}
+                            Err("break") => break,
+                            Err(x) => panic!("{x}"),
+                        }
+                    }
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc,
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => self.cancel(),
+
                 Event::Key(KeyEvent {
                     code: KeyCode::Char('q'),
                     modifiers: KeyModifiers::NONE,
@@ -230,8 +1142,8 @@ impl GameState {
                         if *i > 0 {
                             *i -= 1;
                         }
-                    } else if !self.history.is_empty() {
-                        self.looking_back_at = Some(self.history.len() - 1);
+                    } else if self.tree.depth() > 0 {
+                        self.looking_back_at = Some(self.tree.depth() - 1);
                     }
 
                     self.render();
@@ -250,7 +1162,7 @@ impl GameState {
                     modifiers: KeyModifiers::NONE,
                 }) => {
                     if let Some(i) = self.looking_back_at.as_mut() {
-                        if *i < self.history.len() - 1 {
+                        if *i < self.tree.depth() - 1 {
                             *i += 1;
                         } else {
                             self.looking_back_at = None;
@@ -260,6 +1172,13 @@ impl GameState {
                     self.render();
                 }
 
+                Event::Mouse(MouseEvent {
+                    kind: MouseEventKind::Down(MouseButton::Left),
+                    column: MOVE_LIST_COLUMN..,
+                    row: row @ 1..=MOVE_LIST_ROWS,
+                    modifiers: KeyModifiers::NONE,
+                }) => self.click_move_list(row),
+
                 Event::Mouse(MouseEvent {
                     kind: MouseEventKind::Down(MouseButton::Left),
                     column,
@@ -303,25 +1222,191 @@ impl GameState {
 
                     let d = (c as u8 - b'0') as u32;
 
-                    let best_move = self.board.best_move(
-                        self.turn,
-                        d,
-                        &mut self.trans_table,
-                    );
+                    // A ponder search of this position that already got as
+                    // deep answers right away
+                    let pondered = self
+                        .take_ponder_result()
+                        .map(|(result, _)| result)
+                        .filter(|result| {
+                            result.depth >= d && self.analysis_lines == 1
+                        });
+
+                    let ponder_hit = pondered.is_some();
+
+                    // MCTS thinks for `d` seconds instead, or half a second
+                    // for 0
+                    let mut results = match (self.engine, pondered) {
+                        (_, Some(result)) => vec![result],
+                        (Engine::AlphaBeta, None) => {
+                            self.searcher.search_multi_pv(
+                                self.board,
+                                self.turn,
+                                d,
+                                self.analysis_lines,
+                                |_| {},
+                            )
+                        }
+                        (Engine::Mcts, None) => vec![
+                            self.mcts
+                                .think_for(
+                                    self.board,
+                                    self.turn,
+                                    Duration::from_secs(d as u64)
+                                        .max(Duration::from_millis(500)),
+                                )
+                                .expect("The game ends without legal moves"),
+                        ],
+                    };
 
                     let t = t.elapsed();
 
+                    let [from, to] = results[0].best_move();
+
                     println!(
-                        "Best move: {} -> {}, score: {}, \
-                        Took: {t:.2?} at depth: {d}",
-                        to_readable_coord(best_move.0[0]),
-                        to_readable_coord(best_move.0[1]),
-                        best_move.1,
+                        "Best move: {} -> {}, {}, Took: {t:.2?} {}{}",
+                        to_readable_coord(from),
+                        to_readable_coord(to),
+                        describe_score(results[0].score),
+                        match self.engine {
+                            Engine::AlphaBeta => {
+                                format!("at depth: {}", results[0].depth)
+                            }
+                            Engine::Mcts => {
+                                format!("with {} playouts", results[0].nodes)
+                            }
+                        },
+                        if ponder_hit { " (pondered)" } else { "" },
+                    );
+
+                    if results.len() == 1 {
+                        println!(
+                            "Principal variation: {}",
+                            format_pv(&results[0].pv)
+                        );
+                    } else {
+                        print_analysis(&results);
+                    }
+
+                    self.pv = results.swap_remove(0).pv;
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('m'),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => {
+                    self.analysis_lines = match self.analysis_lines {
+                        1 => 3,
+                        3 => 5,
+                        _ => 1,
+                    };
+
+                    self.render();
+                    execute!(
+                        self.out,
+                        terminal::Clear(terminal::ClearType::FromCursorDown)
+                    )
+                    .unwrap();
+                    println!("Analysis lines: {}", self.analysis_lines);
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('e'),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => {
+                    self.engine = match self.engine {
+                        Engine::AlphaBeta => Engine::Mcts,
+                        Engine::Mcts => Engine::AlphaBeta,
+                    };
+
+                    self.render();
+                    execute!(
+                        self.out,
+                        terminal::Clear(terminal::ClearType::FromCursorDown)
+                    )
+                    .unwrap();
+                    println!("Engine: {:?}", self.engine);
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('v'),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => {
+                    self.show_pv = !self.show_pv;
+
+                    self.render();
+                }
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c @ ('u' | 'r')),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => match self.step_history(c == 'r') {
+                    Ok(()) => {}
+                    Err("break") => break,
+                    Err(x) => panic!("{x}"),
+                },
+
+                Event::Key(KeyEvent {
+                    code: code @ (KeyCode::Up | KeyCode::Down),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => match self.switch_variation(if code == KeyCode::Up {
+                    -1
+                } else {
+                    1
+                }) {
+                    Ok(()) => {}
+                    Err("break") => break,
+                    Err(x) => panic!("{x}"),
+                },
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('x'),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => self.export(),
+
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('p'),
+                    modifiers: KeyModifiers::NONE,
+                    kind: KeyEventKind::Press,
+                    state: _,
+                }) => {
+                    self.pondering = !self.pondering;
+
+                    self.render();
+                    execute!(
+                        self.out,
+                        terminal::Clear(terminal::ClearType::FromCursorDown)
+                    )
+                    .unwrap();
+                    println!(
+                        "Pondering: {}",
+                        if self.pondering { "on" } else { "off" }
                     );
                 }
 
                 _ => {}
             }
+
+            self.update_ponder();
+        }
+
+        self.exit();
+    }
+
+    fn exit(&mut self) {
+        if let Some(ponder) = self.ponder.take() {
+            ponder.stop();
         }
 
         execute!(
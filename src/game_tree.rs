@@ -0,0 +1,235 @@
+use bitarray::BitArray;
+
+use crate::{
+    board::{BoardState, M, to_linind},
+    bot::Move,
+    clock::Clock,
+    selfplay::{GameRecord, Outcome},
+};
+
+// How the game ended in a position: the outcome, and why when it wasn't a
+// winning move, like "Black ran out of time"
+#[derive(Debug, Clone)]
+pub struct GameEnd {
+    pub outcome: Outcome,
+    pub reason: Option<String>,
+}
+
+struct Node {
+    board: BoardState,
+    // The move into this node, None for the root
+    mv: Option<Move>,
+    parent: Option<usize>,
+    // The first child is the main line, the others are variations
+    children: Vec<usize>,
+    // The child that going forward returns to
+    last_child: usize,
+    // How the game ended here, if it did
+    end: Option<GameEnd>,
+    // The clocks as the position was reached, in games with a time control
+    clocks: Option<[Clock; 2]>,
+}
+
+// The moves of a game with every variation tried from its positions. Going
+// back and playing a different move adds a variation instead of losing the
+// line that was there.
+pub struct GameTree {
+    nodes: Vec<Node>,
+    // The nodes from the root to the current one
+    path: Vec<usize>,
+}
+
+impl GameTree {
+    pub fn new(board: BoardState, clocks: Option<[Clock; 2]>) -> Self {
+        Self {
+            nodes: vec![Node {
+                board,
+                mv: None,
+                parent: None,
+                children: Vec::new(),
+                last_child: 0,
+                end: None,
+                clocks,
+            }],
+            path: vec![0],
+        }
+    }
+
+    fn current(&self) -> usize {
+        *self.path.last().unwrap()
+    }
+
+    pub fn board(&self) -> BoardState {
+        self.nodes[self.current()].board
+    }
+
+    // Moves made to reach the current position
+    pub fn depth(&self) -> usize {
+        self.path.len() - 1
+    }
+
+    // The position after `depth` moves on the way to the current one
+    pub fn board_at(&self, depth: usize) -> BoardState {
+        self.nodes[self.path[depth]].board
+    }
+
+    // The move that led to the position after `depth` moves, None for the
+    // start
+    pub fn move_at(&self, depth: usize) -> Option<Move> {
+        self.nodes[self.path[depth]].mv
+    }
+
+    // Squares whose pieces were captured by the move to `depth`
+    pub fn captures_at(&self, depth: usize) -> BitArray<M> {
+        let node = &self.nodes[self.path[depth]];
+
+        let (Some([from, _]), Some(parent)) = (node.mv, node.parent) else {
+            return BitArray::new();
+        };
+
+        let mut captured =
+            node.board.empties() & !self.nodes[parent].board.empties();
+        captured.set(to_linind(from).unwrap(), false);
+
+        captured
+    }
+
+    pub fn end(&self) -> Option<&GameEnd> {
+        self.nodes[self.current()].end.as_ref()
+    }
+
+    // Records that the game ended in the current position
+    pub fn set_end(&mut self, end: GameEnd) {
+        let current = self.current();
+        self.nodes[current].end = Some(end);
+    }
+
+    pub fn clocks(&self) -> Option<[Clock; 2]> {
+        self.nodes[self.current()].clocks
+    }
+
+    // Goes to the child for `mv`, adding it as a new variation if it hasn't
+    // been played here before. `board` is the position after the move and
+    // `clocks` the time left after it, which replaces the time from any
+    // earlier visit.
+    pub fn play(
+        &mut self,
+        mv: Move,
+        board: BoardState,
+        clocks: Option<[Clock; 2]>,
+    ) {
+        let current = self.current();
+
+        let existing = self.nodes[current]
+            .children
+            .iter()
+            .position(|&child| self.nodes[child].mv == Some(mv));
+
+        let i = existing.unwrap_or_else(|| {
+            self.nodes.push(Node {
+                board,
+                mv: Some(mv),
+                parent: Some(current),
+                children: Vec::new(),
+                last_child: 0,
+                end: None,
+                clocks,
+            });
+
+            let child = self.nodes.len() - 1;
+            let children = &mut self.nodes[current].children;
+            children.push(child);
+            children.len() - 1
+        });
+
+        let child = self.nodes[current].children[i];
+
+        self.nodes[current].last_child = i;
+        self.nodes[child].clocks = clocks;
+        self.path.push(child);
+    }
+
+    // Goes back one move, returning whether there was one
+    pub fn back(&mut self) -> bool {
+        if self.path.len() == 1 {
+            return false;
+        }
+
+        self.path.pop();
+
+        true
+    }
+
+    // Goes forward along the variation last visited from here
+    pub fn forward(&mut self) -> bool {
+        let node = &self.nodes[self.current()];
+
+        let Some(&child) = node.children.get(node.last_child) else {
+            return false;
+        };
+
+        self.path.push(child);
+
+        true
+    }
+
+    // The variation the current move belongs to at its position, and how many
+    // there are
+    pub fn variation(&self) -> (usize, usize) {
+        let current = self.current();
+
+        let Some(parent) = self.nodes[current].parent else {
+            return (0, 1);
+        };
+
+        let siblings = &self.nodes[parent].children;
+
+        (
+            siblings.iter().position(|&x| x == current).unwrap(),
+            siblings.len(),
+        )
+    }
+
+    // Switches the current move for the one `offset` variations over, which
+    // wraps around
+    pub fn switch_variation(&mut self, offset: isize) {
+        let (i, count) = self.variation();
+
+        let Some(parent) = self.nodes[self.current()].parent else {
+            return;
+        };
+
+        let i = (i as isize + offset).rem_euclid(count as isize) as usize;
+
+        self.nodes[parent].last_child = i;
+        *self.path.last_mut().unwrap() = self.nodes[parent].children[i];
+    }
+
+    // Every line from the start to the end of a variation, main line first.
+    // Lines where the game didn't end are unfinished.
+    pub fn records(&self) -> Vec<GameRecord> {
+        let mut records = Vec::new();
+        let mut stack = vec![(0, Vec::new())];
+
+        while let Some((i, mut moves)) = stack.pop() {
+            let node = &self.nodes[i];
+            moves.extend(node.mv);
+
+            if node.children.is_empty() {
+                records.push(GameRecord {
+                    outcome: node
+                        .end
+                        .as_ref()
+                        .map_or(Outcome::Unfinished, |end| end.outcome),
+                    moves,
+                });
+            } else {
+                for &child in node.children.iter().rev() {
+                    stack.push((child, moves.clone()));
+                }
+            }
+        }
+
+        records
+    }
+}
@@ -1,19 +1,85 @@
 #![feature(iter_array_chunks)]
 
-use std::env;
+use std::{env, time::Duration};
 
-use board::{BoardState, HighlightedBoardState, TOWERS};
+use board::{BoardState, Faction, HighlightedBoardState, TOWERS};
+use book::Book;
+use bot::{BotConfig, SearchOptions};
+use clock::TimeControl;
+use eval::EvalWeights;
 use game::GameState;
+use nnue::Network;
+use stats::Sprt;
+use tablebase::Tablebase;
 
+mod bench;
 mod board;
+mod book;
 mod bot;
+mod clock;
+mod eval;
 mod game;
+mod game_tree;
 mod magic_bitboards;
+mod mcts;
+mod nnue;
+mod ponder;
+mod rng;
+mod selfplay;
+mod stats;
+mod tablebase;
+mod trans_table;
+mod tune;
+
+fn parse_search_options(flags: impl Iterator<Item = String>) -> SearchOptions {
+    let mut options = SearchOptions::default();
+
+    for flag in flags {
+        options.set(&flag).unwrap();
+    }
+
+    options
+}
+
+// Removes `flag` and the value after it from `args`, returning the value
+fn take_flag(
+    args: &mut Vec<String>,
+    flag: &str,
+) -> Result<Option<String>, String> {
+    let Some(i) = args.iter().position(|x| x == flag) else {
+        return Ok(None);
+    };
+
+    let value = args
+        .get(i + 1)
+        .ok_or_else(|| format!("{flag} needs a value"))?
+        .clone();
+
+    args.drain(i..i + 2);
+
+    Ok(Some(value))
+}
 
 fn main() {
-    let mut args = env::args();
+    let mut args: Vec<_> = env::args().skip(1).collect();
 
-    args.next();
+    if let Some(path) = take_flag(&mut args, "--eval-weights").unwrap() {
+        EvalWeights::load(&path).unwrap().set_startup();
+    }
+
+    if let Some(path) = take_flag(&mut args, "--nnue").unwrap() {
+        Network::load(&path).unwrap().set_startup();
+    }
+
+    if let Some(dir) = take_flag(&mut args, "--tablebase").unwrap() {
+        Tablebase::load(&dir).unwrap().set_startup();
+    }
+
+    if let Some(path) = take_flag(&mut args, "--book").unwrap() {
+        Book::load(&path).unwrap().set_startup();
+    }
+
+    let mut args = args.into_iter();
 
     match args.next().as_deref() {
         Some("test") => {
@@ -31,11 +97,109 @@ fn main() {
 
             let board = BoardState::standard_setup();
 
-            println!("{}", HighlightedBoardState(board, TOWERS));
+            println!("{}", HighlightedBoardState::new(board, TOWERS));
         }
-        _ => {
+        Some("bench") => {
+            let depth = args.next().map_or(3, |x| x.parse().unwrap());
+
+            bench::run(depth, parse_search_options(args));
+        }
+        Some("bench-smp") => {
+            let depth = args.next().map_or(3, |x| x.parse().unwrap());
+            let max_threads = args.next().map_or(4, |x| x.parse().unwrap());
+
+            bench::smp_scaling(depth, max_threads, parse_search_options(args));
+        }
+        Some("match") => {
+            let games = args.next().map_or(10, |x| x.parse().unwrap());
+            let a = BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+            let b = BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+            let elo0 = args.next().map_or(0.0, |x| x.parse().unwrap());
+            let elo1 = args.next().map_or(10.0, |x| x.parse().unwrap());
+
+            selfplay::run_match(&a, &b, games, &Sprt::new(elo0, elo1));
+        }
+        Some("selfplay") => {
+            let games = args.next().map_or(10, |x| x.parse().unwrap());
+            let config =
+                BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+            let path = args.next().expect("Missing output file");
+
+            selfplay::generate(&config, games, &path);
+        }
+        Some("tune") => {
+            let corpus = args.next().expect("Missing corpus file");
+            let out = args.next().expect("Missing output file");
+            let passes = args.next().map_or(100, |x| x.parse().unwrap());
+
+            tune::run(&corpus, &out, passes);
+        }
+        Some("tablebase") => {
+            let dir = args.next().expect("Missing output directory");
+            let pieces = args.next().map_or(2, |x| x.parse().unwrap());
+
+            Tablebase::generate(&dir, pieces).unwrap();
+        }
+        Some("book") => {
+            let corpus = args.next().expect("Missing corpus file");
+            let out = args.next().expect("Missing output file");
+            let plies = args.next().map_or(12, |x| x.parse().unwrap());
+            let min_games = args.next().map_or(2, |x| x.parse().unwrap());
+
+            book::generate(&corpus, &out, plies, min_games);
+        }
+        Some("play") => {
+            let bot_faction = match args.next().as_deref() {
+                Some("black") => Faction::White,
+                Some("white") => Faction::Black,
+                _ => panic!("Choose a side to play: black or white"),
+            };
+            let config =
+                BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+
+            let mut game = GameState::against_bot(bot_faction, config);
+
+            if let Some(control) = args.next() {
+                game = game
+                    .with_time_control(TimeControl::parse(&control).unwrap());
+            }
+
+            game.run();
+        }
+        Some("watch") => {
+            let black =
+                BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+            let white =
+                BotConfig::parse(&args.next().unwrap_or_default()).unwrap();
+            let delay = args.next().map_or(1.0, |x| x.parse().unwrap());
+
+            let mut game = GameState::bot_vs_bot(
+                [black, white],
+                Duration::from_secs_f64(delay),
+            );
+
+            if let Some(control) = args.next() {
+                game = game
+                    .with_time_control(TimeControl::parse(&control).unwrap());
+            }
+
+            game.run();
+        }
+        Some("analyse") => {
+            let depth = args.next().map_or(3, |x| x.parse().unwrap());
+            let line = args.next().unwrap_or_default();
+
+            bench::analyse(&line, depth, parse_search_options(args));
+        }
+        // Two players at one keyboard, with an optional time control
+        control => {
             let mut game = GameState::new();
 
+            if let Some(control) = control {
+                game = game
+                    .with_time_control(TimeControl::parse(control).unwrap());
+            }
+
             game.run();
         }
     }
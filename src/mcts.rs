@@ -0,0 +1,342 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    board::{BoardState, Faction},
+    bot::{
+        BotConfig, Move, Score, SearchOptions, SearchResult, WIN_SCORE,
+        perspective,
+    },
+    eval::Evaluator,
+    rng::Rng,
+};
+
+// Exploration constant of UCT
+const EXPLORATION: f64 = 1.4;
+
+// Random playouts still going after this many plies are scored as draws
+const PLAYOUT_PLIES: u32 = 200;
+
+// Random moves played before evaluating the position in eval playouts
+const EVAL_PLAYOUT_PLIES: u32 = 4;
+
+// The search stops once its tree has this many nodes, around 100 MB
+const MAX_NODES: usize = 1 << 20;
+
+// Scores are mapped to win probabilities and back with a logistic curve, with
+// this many points for 10:1 odds
+const SCORE_SCALE: f64 = 400.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playout {
+    // Random moves until the game ends
+    Random,
+    // A few random moves, then the static evaluation as a win probability
+    Eval,
+}
+
+struct Node {
+    // The move into this node, unused for the root
+    mv: Move,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // Moves without a child node yet, generated on the first visit
+    untried: Option<Vec<Move>>,
+    visits: u32,
+    // Sum of playout results for the faction that made `mv`
+    wins: f64,
+    // Whether `mv` won the game
+    won: bool,
+}
+
+impl Node {
+    fn new(mv: Move, parent: Option<usize>, won: bool) -> Self {
+        Self {
+            mv,
+            parent,
+            children: Vec::new(),
+            untried: None,
+            visits: 0,
+            wins: 0.0,
+            won,
+        }
+    }
+}
+
+fn win_probability(score: Score) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-score as f64 / SCORE_SCALE))
+}
+
+pub fn probability_to_score(p: f64) -> Score {
+    let p = p.clamp(0.001, 0.999);
+
+    (SCORE_SCALE * (p / (1.0 - p)).log10()) as Score
+}
+
+// Monte Carlo tree search with UCT selection
+pub struct Mcts {
+    pub evaluator: Arc<dyn Evaluator>,
+    pub playout: Playout,
+    // Playouts made by the last search
+    pub playouts: u64,
+    rng: Rng,
+    nodes: Vec<Node>,
+    // The tafl evaluation is not zero for the standard setup, so eval
+    // playouts score positions relative to it
+    baseline: Score,
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Self::new(&SearchOptions::default(), Playout::Eval)
+    }
+}
+
+impl Mcts {
+    pub fn new(options: &SearchOptions, playout: Playout) -> Self {
+        let evaluator = options.eval.evaluator();
+
+        Self {
+            baseline: evaluator.evaluate(BoardState::standard_setup()),
+            evaluator,
+            playout,
+            playouts: 0,
+            rng: Rng::new(0),
+            nodes: Vec::new(),
+        }
+    }
+
+    // Result of the game for white, between 0 and 1, from playing on from
+    // `board`
+    fn play_out(&mut self, mut board: BoardState, mut turn: Faction) -> f64 {
+        let plies = match self.playout {
+            Playout::Random => PLAYOUT_PLIES,
+            Playout::Eval => EVAL_PLAYOUT_PLIES,
+        };
+
+        for _ in 0..plies {
+            if turn == Faction::White && board.king_escapes().count_ones() > 0 {
+                return 1.0;
+            }
+
+            let moves: Vec<_> = board.all_moves(turn).collect();
+
+            if moves.is_empty() {
+                return white_result(turn.other_faction());
+            }
+
+            let [from, to] = moves[self.rng.below(moves.len())];
+
+            if board.do_move(from, to) {
+                return white_result(turn);
+            }
+
+            turn = turn.other_faction();
+        }
+
+        match self.playout {
+            Playout::Random => 0.5,
+            Playout::Eval => {
+                win_probability(self.evaluator.evaluate(board) - self.baseline)
+            }
+        }
+    }
+
+    // The child of `i` with the highest upper confidence bound
+    fn select_child(&self, i: usize) -> usize {
+        let log_visits = (self.nodes[i].visits as f64).ln();
+
+        *self.nodes[i]
+            .children
+            .iter()
+            .max_by(|&&a, &&b| {
+                let ucb = |j: usize| {
+                    let node = &self.nodes[j];
+                    let visits = node.visits as f64;
+
+                    node.wins / visits
+                        + EXPLORATION * (log_visits / visits).sqrt()
+                };
+
+                ucb(a).total_cmp(&ucb(b))
+            })
+            .unwrap()
+    }
+
+    // One round of selection, expansion, playout and backpropagation
+    fn iterate(&mut self, mut board: BoardState, mut turn: Faction) {
+        let mut i = 0;
+
+        // White relative result of the game from the selected node
+        let result = loop {
+            if self.nodes[i].won {
+                break white_result(turn.other_faction());
+            }
+
+            let untried = self.nodes[i]
+                .untried
+                .get_or_insert_with(|| board.all_moves(turn).collect());
+
+            if !untried.is_empty() {
+                let j = self.rng.below(untried.len());
+                let [from, to] = untried.swap_remove(j);
+
+                let won = board.do_move(from, to);
+                turn = turn.other_faction();
+
+                self.nodes.push(Node::new([from, to], Some(i), won));
+                let child = self.nodes.len() - 1;
+                self.nodes[i].children.push(child);
+
+                i = child;
+
+                break if won {
+                    white_result(turn.other_faction())
+                } else {
+                    self.play_out(board, turn)
+                };
+            }
+
+            if self.nodes[i].children.is_empty() {
+                // No legal moves, so the faction to move loses
+                break white_result(turn.other_faction());
+            }
+
+            i = self.select_child(i);
+
+            let [from, to] = self.nodes[i].mv;
+            board.do_move(from, to);
+            turn = turn.other_faction();
+        };
+
+        // `turn` is now the faction to move at node `i`, so the move into it
+        // was made by the other one
+        let mut mover = turn.other_faction();
+        let mut node = Some(i);
+
+        while let Some(j) = node {
+            self.nodes[j].visits += 1;
+            self.nodes[j].wins += match mover {
+                Faction::White => result,
+                Faction::Black => 1.0 - result,
+            };
+
+            mover = mover.other_faction();
+            node = self.nodes[j].parent;
+        }
+
+        self.playouts += 1;
+    }
+
+    // The most visited path from the root
+    fn principal_variation(&self) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut i = 0;
+
+        while let Some(&child) = self.nodes[i]
+            .children
+            .iter()
+            .max_by_key(|&&j| self.nodes[j].visits)
+        {
+            pv.push(self.nodes[child].mv);
+            i = child;
+        }
+
+        pv
+    }
+
+    // Runs `playouts` iterations, at least one, or until `deadline` has
+    // passed or the tree is full. Fails if `turn` has no legal moves.
+    pub fn search(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        playouts: u64,
+        deadline: Option<Instant>,
+    ) -> Result<SearchResult, String> {
+        let start_time = Instant::now();
+
+        let moves: Vec<_> = board.all_moves(turn).collect();
+
+        if moves.is_empty() {
+            return Err(format!("{turn:?} has no legal moves"));
+        }
+
+        let mut root = Node::new([[0; 2]; 2], None, false);
+        root.untried = Some(moves);
+
+        self.nodes.clear();
+        self.nodes.push(root);
+        self.playouts = 0;
+
+        loop {
+            self.iterate(board, turn);
+
+            if self.playouts >= playouts
+                || self.nodes.len() >= MAX_NODES
+                || (self.playouts.is_multiple_of(64)
+                    && deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline))
+            {
+                break;
+            }
+        }
+
+        let pv = self.principal_variation();
+
+        let best = self.nodes[0]
+            .children
+            .iter()
+            .map(|&j| &self.nodes[j])
+            .max_by_key(|node| node.visits)
+            .expect("The first iteration expands the root");
+
+        let score = if best.won {
+            WIN_SCORE - 1
+        } else {
+            probability_to_score(best.wins / best.visits as f64)
+        };
+
+        Ok(SearchResult {
+            depth: pv.len() as u32,
+            pv,
+            score: score * perspective(turn),
+            nodes: self.playouts,
+            time: start_time.elapsed(),
+        })
+    }
+
+    pub fn think(
+        &mut self,
+        config: &BotConfig,
+        board: BoardState,
+        turn: Faction,
+    ) -> Result<SearchResult, String> {
+        self.search(
+            board,
+            turn,
+            config.playouts,
+            config.time.map(|time| Instant::now() + time),
+        )
+    }
+
+    // Searches for a fixed time, for the TUI
+    pub fn think_for(
+        &mut self,
+        board: BoardState,
+        turn: Faction,
+        time: Duration,
+    ) -> Result<SearchResult, String> {
+        self.search(board, turn, u64::MAX, Some(Instant::now() + time))
+    }
+}
+
+// Result of a game for white
+fn white_result(winner: Faction) -> f64 {
+    match winner {
+        Faction::White => 1.0,
+        Faction::Black => 0.0,
+    }
+}
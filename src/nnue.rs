@@ -0,0 +1,294 @@
+use std::{fs, sync::OnceLock};
+
+use bitarray::BitArray;
+
+use crate::{
+    board::{BoardState, M, W},
+    bot::{Score, WIN_THRESHOLD},
+    eval::Evaluator,
+};
+
+// One input per square for each of the two board planes and for the king
+pub const INPUTS: usize = 3 * W * W;
+
+pub const HIDDEN: usize = 128;
+
+// Hidden activations are clipped to [0, QA], and output weights are scaled
+// by QB
+const QA: i32 = 255;
+const QB: i32 = 64;
+
+// Output of the network for a score of one piece
+const OUTPUT_SCALE: i64 = 100;
+
+const MAGIC: &[u8; 8] = b"HNEFNNUE";
+
+// Network loaded at startup with `--nnue`, used by "eval=nnue"
+static STARTUP_NETWORK: OnceLock<Network> = OnceLock::new();
+
+// A network with one hidden layer. The hidden layer is kept in an
+// `Accumulator`, which can be updated for a move instead of recomputed.
+pub struct Network {
+    // One row of hidden weights per input
+    input_weights: Vec<[i16; HIDDEN]>,
+    hidden_bias: [i16; HIDDEN],
+    output_weights: [i16; HIDDEN],
+    output_bias: i32,
+}
+
+// Hidden layer values before activation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accumulator([i32; HIDDEN]);
+
+fn features(board: BoardState) -> impl Iterator<Item = usize> {
+    let [a, b] = board.0;
+
+    a.trues_iter()
+        .chain(b.trues_iter().map(|i| i + W * W))
+        .chain(board.king().trues_iter().map(|i| i + 2 * W * W))
+}
+
+impl Network {
+    // Reads a weights file: the bytes "HNEFNNUE", the hidden layer size as a
+    // u32, then INPUTS * HIDDEN input weights (input major), HIDDEN hidden
+    // biases and HIDDEN output weights as i16, and the output bias as an i32,
+    // all little endian.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("Could not read {path}: {e}"))?;
+
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| format!("{path} is not a network file"))?;
+
+        let expected_len = 4 + 2 * (INPUTS * HIDDEN + 2 * HIDDEN) + 4;
+
+        if rest.len() != expected_len {
+            return Err(format!(
+                "{path} has the wrong size for {INPUTS} inputs and {HIDDEN} \
+                hidden neurons"
+            ));
+        }
+
+        let (hidden, rest) = rest.split_at(4);
+
+        if u32::from_le_bytes(hidden.try_into().unwrap()) as usize != HIDDEN {
+            return Err(format!(
+                "{path} does not have {HIDDEN} hidden neurons"
+            ));
+        }
+
+        let (weights, output_bias) = rest.split_at(rest.len() - 4);
+
+        let mut weights = weights
+            .chunks_exact(2)
+            .map(|x| i16::from_le_bytes([x[0], x[1]]))
+            .array_chunks::<HIDDEN>();
+
+        let input_weights = weights.by_ref().take(INPUTS).collect();
+        let hidden_bias = weights.next().unwrap();
+        let output_weights = weights.next().unwrap();
+
+        Ok(Self {
+            input_weights,
+            hidden_bias,
+            output_weights,
+            output_bias: i32::from_le_bytes(output_bias.try_into().unwrap()),
+        })
+    }
+
+    // Makes this the network used by "eval=nnue"
+    pub fn set_startup(self) {
+        if STARTUP_NETWORK.set(self).is_err() {
+            panic!("Network already set");
+        }
+    }
+
+    pub fn startup_loaded() -> bool {
+        STARTUP_NETWORK.get().is_some()
+    }
+
+    pub fn startup() -> &'static Self {
+        STARTUP_NETWORK
+            .get()
+            .expect("eval=nnue needs a network loaded with --nnue <file>")
+    }
+
+    pub fn refresh(&self, board: BoardState) -> Accumulator {
+        let mut acc = Accumulator(self.hidden_bias.map(i32::from));
+
+        for i in features(board) {
+            acc.add(&self.input_weights[i]);
+        }
+
+        acc
+    }
+
+    // The accumulator for `after`, given the one for `before`. Only the
+    // inputs that differ between the two are touched, which is a handful for
+    // a move and its captures.
+    pub fn update(
+        &self,
+        acc: &Accumulator,
+        before: BoardState,
+        after: BoardState,
+    ) -> Accumulator {
+        let mut acc = *acc;
+
+        let planes = |board: BoardState| [board.0[0], board.0[1], board.king()];
+
+        for (offset, (old, new)) in (0..)
+            .step_by(W * W)
+            .zip(planes(before).into_iter().zip(planes(after)))
+        {
+            let changed = |a: BitArray<M>, b: BitArray<M>| a & !b;
+
+            for i in changed(old, new).trues_iter() {
+                acc.sub(&self.input_weights[offset + i]);
+            }
+
+            for i in changed(new, old).trues_iter() {
+                acc.add(&self.input_weights[offset + i]);
+            }
+        }
+
+        acc
+    }
+
+    // White relative score. It is computed in i64 so large weights can't
+    // overflow, and kept short of the scores of forced wins.
+    pub fn output(&self, acc: &Accumulator) -> Score {
+        let sum = acc
+            .0
+            .iter()
+            .zip(self.output_weights)
+            .map(|(&x, w)| x.clamp(0, QA) as i64 * w as i64)
+            .sum::<i64>();
+
+        let score =
+            (sum + self.output_bias as i64) * OUTPUT_SCALE / (QA * QB) as i64;
+        let limit = (WIN_THRESHOLD - 1) as i64;
+
+        score.clamp(-limit, limit) as Score
+    }
+}
+
+impl Accumulator {
+    fn add(&mut self, weights: &[i16; HIDDEN]) {
+        for (x, &w) in self.0.iter_mut().zip(weights) {
+            *x += w as i32;
+        }
+    }
+
+    fn sub(&mut self, weights: &[i16; HIDDEN]) {
+        for (x, &w) in self.0.iter_mut().zip(weights) {
+            *x -= w as i32;
+        }
+    }
+}
+
+pub struct NnueEvaluator {
+    pub network: &'static Network,
+}
+
+impl Evaluator for NnueEvaluator {
+    fn evaluate(&self, board: BoardState) -> Score {
+        self.network.output(&self.network.refresh(board))
+    }
+
+    fn network(&self) -> Option<&'static Network> {
+        Some(self.network)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::board::Piece;
+
+    fn network(weight: i16, output_bias: i32) -> Network {
+        Network {
+            input_weights: vec![[weight; HIDDEN]; INPUTS],
+            hidden_bias: [weight; HIDDEN],
+            output_weights: [weight; HIDDEN],
+            output_bias,
+        }
+    }
+
+    #[test]
+    fn output_stays_below_win_scores() {
+        let saturated = Accumulator([i32::MAX; HIDDEN]);
+
+        let high = network(i16::MAX, i32::MAX);
+        assert_eq!(high.output(&saturated), WIN_THRESHOLD - 1);
+        assert_eq!(
+            high.output(&high.refresh(BoardState::standard_setup())),
+            WIN_THRESHOLD - 1
+        );
+
+        let low = network(i16::MIN, i32::MIN);
+        assert_eq!(low.output(&saturated), -(WIN_THRESHOLD - 1));
+    }
+
+    #[test]
+    fn update_matches_refresh() {
+        // Weights that differ between inputs, so a missed input shows
+        let weight = |i: usize| (i * 7919 % 201) as i16 - 100;
+        let network = Network {
+            input_weights: (0..INPUTS)
+                .map(|i| std::array::from_fn(|j| weight(i * HIDDEN + j)))
+                .collect(),
+            ..network(1, 0)
+        };
+
+        let mut board = BoardState::new();
+
+        for (square, piece) in [
+            ([8, 8], Piece::King),
+            ([3, 5], Piece::White),
+            ([6, 2], Piece::White),
+            ([9, 4], Piece::White),
+            ([0, 4], Piece::Black),
+            ([3, 6], Piece::Black),
+            ([6, 3], Piece::Black),
+        ] {
+            board.set_2d(square, piece);
+        }
+
+        // A capture by black, a king move, a quiet move and a capture by
+        // white
+        let line = [
+            [[0, 4], [3, 4]],
+            [[8, 8], [8, 3]],
+            [[3, 6], [3, 9]],
+            [[9, 4], [6, 4]],
+        ];
+
+        let mut stack = vec![(board, network.refresh(board))];
+
+        for [from, to] in line {
+            let (before, acc) = *stack.last().unwrap();
+
+            let mut after = before;
+            assert!(!after.do_move(from, to));
+
+            let acc = network.update(&acc, before, after);
+            assert_eq!(acc, network.refresh(after));
+
+            stack.push((after, acc));
+        }
+
+        let pieces = |board: BoardState| (board.0[0] | board.0[1]).count_ones();
+        assert_eq!(pieces(stack.last().unwrap().0), pieces(board) - 2);
+
+        // Unmaking a move gets back the accumulator from before it
+        for pair in stack.windows(2).rev() {
+            let [(before, old), (after, new)] = pair else {
+                unreachable!()
+            };
+
+            assert_eq!(network.update(new, *after, *before), *old);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    board::{BoardState, Faction},
+    bot::{SearchResult, Searcher},
+};
+
+// Pondering goes on until stopped, so this only bounds the iterations
+const PONDER_DEPTH: u32 = 64;
+
+// A search of a position in a background thread, run while waiting for the
+// human so the transposition table is warm, or the answer ready, once the bot
+// is asked for a move
+pub struct Ponder {
+    pub board: BoardState,
+    pub turn: Faction,
+    started: Instant,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<SearchResult>,
+}
+
+impl Ponder {
+    // Starts searching with the options and transposition table of `searcher`
+    pub fn start(
+        searcher: &Searcher,
+        board: BoardState,
+        turn: Faction,
+    ) -> Self {
+        let mut ponderer = searcher.ponderer();
+        let stop = ponderer.stop.clone();
+
+        let handle = thread::spawn(move || {
+            ponderer.search(board, turn, PONDER_DEPTH, |_| {})
+        });
+
+        Self {
+            board,
+            turn,
+            started: Instant::now(),
+            stop,
+            handle,
+        }
+    }
+
+    pub fn is_for(&self, board: BoardState, turn: Faction) -> bool {
+        self.board == board && self.turn == turn
+    }
+
+    // Stops the search, returning its deepest finished iteration and how
+    // long it searched
+    pub fn stop(self) -> (SearchResult, Duration) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        let elapsed = self.started.elapsed();
+
+        (self.handle.join().unwrap(), elapsed)
+    }
+}
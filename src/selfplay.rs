@@ -0,0 +1,273 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use ahash::RandomState;
+use hashbrown::HashMap;
+
+use crate::{
+    board::{BoardState, Faction, from_readable_move},
+    bot::{Bot, BotConfig, Move, format_pv},
+    rng::Rng,
+    stats::{MatchScore, Sprt, SprtVerdict},
+};
+
+// Games between bots running longer than this are drawn, here and when
+// watching them in the TUI
+pub const MAX_GAME_PLIES: usize = 300;
+
+const OPENING_PLIES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win(Faction),
+    Draw,
+    // A line exported from the TUI that stops before the game ended
+    Unfinished,
+}
+
+// A game, written as one line with the outcome ("white", "black", "draw" or
+// "unfinished") followed by the moves, like "white D1-D3 F4-C4 ..."
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub outcome: Outcome,
+    pub moves: Vec<Move>,
+}
+
+impl GameRecord {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut words = s.split_whitespace();
+
+        let outcome = match words.next()? {
+            "white" => Outcome::Win(Faction::White),
+            "black" => Outcome::Win(Faction::Black),
+            "draw" => Outcome::Draw,
+            "unfinished" => Outcome::Unfinished,
+            _ => return None,
+        };
+
+        let moves: Vec<Move> =
+            words.map(from_readable_move).collect::<Option<_>>()?;
+
+        // Every move has to be legal, and none can follow a winning move
+        let mut board = BoardState::standard_setup();
+        let mut turn = Faction::Black;
+        let mut won = false;
+
+        for &mv in &moves {
+            if won || !board.all_moves(turn).any(|x| x == mv) {
+                return None;
+            }
+
+            won = board.do_move(mv[0], mv[1]);
+            turn = turn.other_faction();
+        }
+
+        Some(Self { outcome, moves })
+    }
+
+    // Every position of the game with the faction to move, from the standard
+    // setup up to but not including the final position. The moves were
+    // checked to be legal by `parse`.
+    pub fn positions(&self) -> impl Iterator<Item = (BoardState, Faction)> {
+        self.moves.iter().scan(
+            (BoardState::standard_setup(), Faction::Black),
+            |(board, turn), &[from, to]| {
+                let position = (*board, *turn);
+
+                board.do_move(from, to);
+                *turn = turn.other_faction();
+
+                Some(position)
+            },
+        )
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.outcome {
+            Outcome::Win(Faction::White) => write!(f, "white")?,
+            Outcome::Win(Faction::Black) => write!(f, "black")?,
+            Outcome::Draw => write!(f, "draw")?,
+            Outcome::Unfinished => write!(f, "unfinished")?,
+        }
+
+        if !self.moves.is_empty() {
+            write!(f, " {}", format_pv(&self.moves))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Random legal moves from the standard setup, the same for a given seed
+pub fn random_opening(seed: u64, plies: usize) -> Vec<Move> {
+    let mut rng = Rng::new(seed);
+
+    let mut board = BoardState::standard_setup();
+    let mut turn = Faction::Black;
+    let mut moves = Vec::new();
+
+    for _ in 0..plies {
+        let legal_moves: Vec<_> = board.all_moves(turn).collect();
+
+        if legal_moves.is_empty() {
+            break;
+        }
+
+        let [from, to] = legal_moves[rng.below(legal_moves.len())];
+
+        if board.do_move(from, to) {
+            break;
+        }
+
+        moves.push([from, to]);
+        turn = turn.other_faction();
+    }
+
+    moves
+}
+
+// Plays out a game between `configs`, indexed by faction, after the given
+// opening. Threefold repetition is a draw.
+pub fn play_game(configs: [&BotConfig; 2], opening: &[Move]) -> GameRecord {
+    let mut bots = configs.map(Bot::new);
+
+    let mut board = BoardState::standard_setup();
+    let mut turn = Faction::Black;
+
+    let mut seen = HashMap::<_, u32, RandomState>::default();
+    let mut moves = Vec::new();
+
+    for ply in 0..MAX_GAME_PLIES {
+        if board.all_moves(turn).next().is_none() {
+            return GameRecord {
+                outcome: Outcome::Win(turn.other_faction()),
+                moves,
+            };
+        }
+
+        let [from, to] = match opening.get(ply) {
+            Some(&mv) => mv,
+            None => bots[turn as usize]
+                .think(configs[turn as usize], board, turn)
+                .expect("Checked for legal moves above")
+                .best_move(),
+        };
+
+        moves.push([from, to]);
+
+        if board.do_move(from, to) {
+            return GameRecord {
+                outcome: Outcome::Win(turn),
+                moves,
+            };
+        }
+
+        turn = turn.other_faction();
+
+        let repetitions = seen.entry((board, turn)).or_default();
+        *repetitions += 1;
+
+        if *repetitions == 3 {
+            return GameRecord {
+                outcome: Outcome::Draw,
+                moves,
+            };
+        }
+    }
+
+    GameRecord {
+        outcome: Outcome::Draw,
+        moves,
+    }
+}
+
+// Plays `games` games between `a` and `b`, alternating colours over a
+// random opening per pair of games, and prints the result for `a`. The match
+// ends early once `sprt` accepts either hypothesis after a pair of games.
+pub fn run_match(a: &BotConfig, b: &BotConfig, games: usize, sprt: &Sprt) {
+    let mut score = MatchScore::default();
+
+    for game in 0..games {
+        let opening = random_opening(game as u64 / 2, OPENING_PLIES);
+
+        let a_faction = [Faction::Black, Faction::White][game % 2];
+
+        let configs = match a_faction {
+            Faction::Black => [a, b],
+            Faction::White => [b, a],
+        };
+
+        let outcome = play_game(configs, &opening).outcome;
+
+        match outcome {
+            Outcome::Win(winner) if winner == a_faction => score.wins += 1,
+            Outcome::Win(_) => score.losses += 1,
+            Outcome::Draw => score.draws += 1,
+            Outcome::Unfinished => unreachable!(),
+        }
+
+        println!(
+            "Game {:3}: A as {a_faction:?}, opening {}, {}",
+            game + 1,
+            format_pv(&opening),
+            match outcome {
+                Outcome::Win(winner) => format!("{winner:?} wins"),
+                Outcome::Draw => "draw".to_owned(),
+                Outcome::Unfinished => "unfinished".to_owned(),
+            },
+        );
+
+        if game % 2 == 1 && sprt.verdict(&score) != SprtVerdict::Undecided {
+            break;
+        }
+    }
+
+    let (elo, [low, high]) = score.elo();
+    let [lower, upper] = sprt.bounds();
+
+    println!("A vs B: {score}");
+    println!("Elo difference: {elo:.1} (95% interval {low:.1} to {high:.1})");
+    println!(
+        "SPRT elo0={} elo1={}: LLR {:.2} (bounds {lower:.2}, {upper:.2}), {}",
+        sprt.elo0,
+        sprt.elo1,
+        score.llr([sprt.elo0, sprt.elo1]),
+        match sprt.verdict(&score) {
+            SprtVerdict::H1 => "H1 accepted",
+            SprtVerdict::H0 => "H0 accepted",
+            SprtVerdict::Undecided => "undecided",
+        },
+    );
+}
+
+// Plays `games` games of `config` against itself from random openings and
+// writes their records to `path`, one per line
+pub fn generate(config: &BotConfig, games: usize, path: &str) {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+
+    for game in 0..games {
+        let opening = random_opening(game as u64, OPENING_PLIES);
+
+        let record = play_game([config, config], &opening);
+
+        writeln!(out, "{record}").unwrap();
+
+        println!(
+            "Game {:3}: {} plies, {}",
+            game + 1,
+            record.moves.len(),
+            match record.outcome {
+                Outcome::Win(winner) => format!("{winner:?} wins"),
+                Outcome::Draw => "draw".to_owned(),
+                Outcome::Unfinished => "unfinished".to_owned(),
+            },
+        );
+    }
+
+    out.flush().unwrap();
+}
@@ -0,0 +1,173 @@
+use std::fmt::Display;
+
+// Elo difference for an expected score, and back
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// Results of a match from the point of view of one side
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    // Average points per game
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // Variance of the points of a single game
+    fn variance(&self) -> f64 {
+        let s = self.score();
+
+        (self.wins as f64 * (1.0 - s).powi(2)
+            + self.losses as f64 * s.powi(2)
+            + self.draws as f64 * (0.5 - s).powi(2))
+            / self.games() as f64
+    }
+
+    // Elo difference with the bounds of its 95% confidence interval. Scores
+    // of 0 or 1 give infinite differences.
+    pub fn elo(&self) -> (f64, [f64; 2]) {
+        let s = self.score();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+
+        let bound = |s: f64| elo(s.clamp(0.0, 1.0));
+
+        (elo(s), [bound(s - margin), bound(s + margin)])
+    }
+
+    // Log likelihood ratio of the Elo difference being `elo1` rather than
+    // `elo0`, with the scores taken as normally distributed
+    pub fn llr(&self, [elo0, elo1]: [f64; 2]) -> f64 {
+        let variance = self.variance();
+
+        if variance == 0.0 {
+            return 0.0;
+        }
+
+        let [s0, s1] = [expected_score(elo0), expected_score(elo1)];
+
+        (s1 - s0) * (2.0 * self.score() - s0 - s1) * self.games() as f64
+            / (2.0 * variance)
+    }
+}
+
+impl Display for MatchScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "+{} -{} ={}, score {:.1}%",
+            self.wins,
+            self.losses,
+            self.draws,
+            self.score() * 100.0
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtVerdict {
+    // The Elo difference is at least elo1
+    H1,
+    // The Elo difference is at most elo0
+    H0,
+    Undecided,
+}
+
+// Sequential probability ratio test between Elo differences of `elo0` and
+// `elo1`, with false positive and false negative rates of `alpha` and `beta`
+#[derive(Debug, Clone, Copy)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    // The LLR below which H0 is accepted and above which H1 is
+    pub fn bounds(&self) -> [f64; 2] {
+        [
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        ]
+    }
+
+    pub fn verdict(&self, score: &MatchScore) -> SprtVerdict {
+        let llr = score.llr([self.elo0, self.elo1]);
+        let [lower, upper] = self.bounds();
+
+        if llr >= upper {
+            SprtVerdict::H1
+        } else if llr <= lower {
+            SprtVerdict::H0
+        } else {
+            SprtVerdict::Undecided
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(wins: u32, losses: u32, draws: u32) -> MatchScore {
+        MatchScore {
+            wins,
+            losses,
+            draws,
+        }
+    }
+
+    #[test]
+    fn elo_estimate() {
+        let (elo, [lower, upper]) = score(3, 1, 0).elo();
+
+        assert!((elo - 190.849).abs() < 0.01, "{elo}");
+        assert!(lower < elo);
+        assert_eq!(upper, f64::INFINITY);
+
+        let (elo, [lower, upper]) = score(40, 40, 20).elo();
+
+        assert_eq!(elo, 0.0);
+        assert!((lower + upper).abs() < 1e-9, "{lower} {upper}");
+    }
+
+    #[test]
+    fn sprt_verdict() {
+        let sprt = Sprt::new(0.0, 10.0);
+        let [lower, upper] = sprt.bounds();
+
+        assert!((lower + 2.944).abs() < 0.001, "{lower}");
+        assert!((upper - 2.944).abs() < 0.001, "{upper}");
+
+        // An even score is more likely with no difference than with 10 Elo
+        let even = score(50, 50, 0);
+
+        assert!(even.llr([0.0, 10.0]) < 0.0);
+        assert_eq!(sprt.verdict(&even), SprtVerdict::Undecided);
+        assert_eq!(sprt.verdict(&score(5000, 5000, 0)), SprtVerdict::H0);
+        assert_eq!(sprt.verdict(&score(600, 300, 100)), SprtVerdict::H1);
+    }
+}
@@ -0,0 +1,898 @@
+use std::{fs, mem, path::Path, sync::OnceLock, time::Instant};
+
+use ahash::RandomState;
+use hashbrown::HashMap;
+
+use bitarray::BitArray;
+
+use crate::board::{
+    BoardState, CORNERS, Faction, M, Piece, SYMMETRIES, TOWERS, W,
+    transform_square,
+};
+
+const MAGIC: &[u8; 8] = b"HNEFTB02";
+
+// Positions per block of a packed table. A probe only reads the runs of one
+// block.
+const BLOCK: usize = 1024;
+
+// Squares the king is moved into by a symmetry of the board before indexing:
+// 0 <= x <= y <= 5, the corner included
+const KING_SQUARES: usize = 21;
+
+// Most pieces besides the king that tables are generated for. The largest
+// table with 4 would hold about 2 billion positions.
+const MAX_GENERATED_PIECES: usize = 3;
+
+// Table loaded at startup with `--tablebase`
+static STARTUP_TABLEBASE: OnceLock<Tablebase> = OnceLock::new();
+
+// Result for the faction to move, counted in plies until the winning move is
+// made. A faction without legal moves has lost in 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TbValue {
+    Win(u32),
+    Loss(u32),
+    Draw,
+}
+
+// Values are stored as one byte: 0 for a draw (or an unsolved position while
+// generating), 1..=127 for a win in that many plies and 128 + n for a loss in
+// n plies
+impl TbValue {
+    fn decode(byte: u8) -> Self {
+        match byte {
+            0 => Self::Draw,
+            1..=127 => Self::Win(byte as u32),
+            _ => Self::Loss(byte as u32 - 128),
+        }
+    }
+
+    fn encode(self) -> Result<u8, String> {
+        match self {
+            Self::Draw => Ok(0),
+            Self::Win(n @ 1..=127) => Ok(n as u8),
+            Self::Loss(n @ 0..=127) => Ok(128 + n as u8),
+            _ => Err(format!("{self:?} is too far for a tablebase")),
+        }
+    }
+
+    fn plies(self) -> u32 {
+        match self {
+            Self::Win(n) | Self::Loss(n) => n,
+            Self::Draw => 0,
+        }
+    }
+}
+
+// Pieces other than the king: white soldiers and black pieces
+type Material = (usize, usize);
+
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+fn material(board: BoardState) -> Material {
+    (
+        (board.whites() & !board.king()).count_ones() as usize,
+        board.blacks().count_ones() as usize,
+    )
+}
+
+fn table_size((whites, blacks): Material) -> usize {
+    KING_SQUARES
+        * binomial(W * W - 1, whites)
+        * binomial(W * W - 1 - whites, blacks)
+}
+
+// Index of the board turned so the king is in the indexed triangle. A king
+// on a diagonal or the middle row or column can get there in more than one
+// way, and then the lowest index is used.
+fn canonical_index(board: BoardState) -> usize {
+    let king = board.king_coord().unwrap();
+
+    (0..SYMMETRIES)
+        .filter(|&s| {
+            let [y, x] = transform_square(king, s);
+            y <= 5 && x <= y
+        })
+        .map(|s| index(board.transformed(s)))
+        .min()
+        .unwrap()
+}
+
+// Index of a set of `squares` in the combinatorial number system, after
+// leaving out the squares in `excluded` from the numbering
+fn combination_index(squares: BitArray<M>, excluded: BitArray<M>) -> usize {
+    squares
+        .trues_iter()
+        .enumerate()
+        .map(|(i, square)| {
+            let rank = square
+                - excluded.trues_iter().take_while(|&x| x < square).count();
+
+            binomial(rank, i + 1)
+        })
+        .sum()
+}
+
+// Inverse of `combination_index` for `k` squares
+fn combination(
+    mut index: usize,
+    k: usize,
+    excluded: BitArray<M>,
+) -> BitArray<M> {
+    let mut squares = BitArray::new();
+
+    for i in (0..k).rev() {
+        let mut rank = i;
+
+        while binomial(rank + 1, i + 1) <= index {
+            rank += 1;
+        }
+
+        index -= binomial(rank, i + 1);
+
+        // Rank back to a square, skipping the excluded ones
+        let mut square = rank;
+
+        for x in excluded.trues_iter() {
+            if x <= square {
+                square += 1;
+            }
+        }
+
+        squares.set(square, true);
+    }
+
+    squares
+}
+
+// Index of a board within its material table, for the king in the indexed
+// triangle
+fn index(board: BoardState) -> usize {
+    let [ky, kx] = board.king_coord().unwrap().map(|x| x as usize);
+    let king_index = ky * (ky + 1) / 2 + kx;
+
+    let whites = board.whites() & !board.king();
+    let blacks = board.blacks();
+
+    let white_count = whites.count_ones() as usize;
+    let black_count = blacks.count_ones() as usize;
+
+    (king_index * binomial(W * W - 1, white_count)
+        + combination_index(whites, board.king()))
+        * binomial(W * W - 1 - white_count, black_count)
+        + combination_index(blacks, board.whites())
+}
+
+// Inverse of `index`. Returns None for indices of impossible positions, the
+// king on a corner or another piece on the throne or a corner, and for boards
+// that are not in canonical form.
+fn board_at(index: usize, (whites, blacks): Material) -> Option<BoardState> {
+    let black_combinations = binomial(W * W - 1 - whites, blacks);
+    let white_combinations = binomial(W * W - 1, whites);
+
+    let black_index = index % black_combinations;
+    let rest = index / black_combinations;
+    let white_index = rest % white_combinations;
+    let king_index = rest / white_combinations;
+
+    let ky = (0..6).rfind(|&y| y * (y + 1) / 2 <= king_index).unwrap();
+    let kx = king_index - ky * (ky + 1) / 2;
+
+    let mut board = BoardState::new();
+    board.set_2d([ky as u16, kx as u16], Piece::King);
+
+    for i in combination(white_index, whites, board.king()).trues_iter() {
+        board.set(i, Piece::White);
+    }
+
+    for i in combination(black_index, blacks, board.whites()).trues_iter() {
+        board.set(i, Piece::Black);
+    }
+
+    (!impossible(board) && canonical_index(board) == index).then_some(board)
+}
+
+// The king on a corner has already won, and other pieces can't stand on the
+// throne or a corner. The second plane holds every piece but the king.
+fn impossible(board: BoardState) -> bool {
+    (board.king() & CORNERS).count_ones() > 0
+        || (board.0[1] & TOWERS).count_ones() > 0
+}
+
+// A solved table stored as runs of equal values: the positions for black to
+// move, then those for white to move, so that the runs are long. Runs are at
+// most 256 long and don't cross blocks.
+struct PackedTable {
+    // Positions for each faction to move
+    size: usize,
+    // Index into `runs` of the first run of each block
+    blocks: Vec<u32>,
+    // The encoded value and the length minus one of each run
+    runs: Vec<[u8; 2]>,
+}
+
+impl PackedTable {
+    // Packs `values` laid out as the solver keeps them, with the positions
+    // for both factions to move side by side
+    fn pack(values: &[u8]) -> Self {
+        let size = values.len() / 2;
+
+        let mut blocks = Vec::new();
+        let mut runs: Vec<[u8; 2]> = Vec::new();
+
+        for i in 0..size * 2 {
+            let value = values[i % size * 2 + i / size];
+            let block_start = i.is_multiple_of(BLOCK);
+
+            if block_start {
+                blocks.push(u32::try_from(runs.len()).expect("Too many runs"));
+            }
+
+            match runs.last_mut() {
+                Some([last, len])
+                    if !block_start && *last == value && *len < u8::MAX =>
+                {
+                    *len += 1
+                }
+                _ => runs.push([value, 0]),
+            }
+        }
+
+        Self { size, blocks, runs }
+    }
+
+    fn get(&self, index: usize, turn: Faction) -> u8 {
+        let i = turn as usize * self.size + index;
+        let mut offset = i % BLOCK;
+
+        for &[value, len] in &self.runs[self.blocks[i / BLOCK] as usize..] {
+            if offset <= len as usize {
+                return value;
+            }
+
+            offset -= len as usize + 1;
+        }
+
+        unreachable!()
+    }
+
+    // The block count, the run count and the block starts as little endian
+    // u64s and u32s, then the runs
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+
+        bytes.extend((self.blocks.len() as u64).to_le_bytes());
+        bytes.extend((self.runs.len() as u64).to_le_bytes());
+
+        for block in &self.blocks {
+            bytes.extend(block.to_le_bytes());
+        }
+
+        bytes.extend(self.runs.as_flattened());
+
+        bytes
+    }
+
+    // Reads a table written by `to_bytes`, checking that every block holds
+    // exactly its share of the `size * 2` positions
+    fn from_bytes(bytes: &[u8], size: usize) -> Option<Self> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+
+        let (counts, rest) = rest.split_at_checked(16)?;
+        let count = |i: usize| {
+            u64::from_le_bytes(counts[i..i + 8].try_into().unwrap()) as usize
+        };
+        let (block_count, run_count) = (count(0), count(8));
+
+        if block_count != (size * 2).div_ceil(BLOCK)
+            || rest.len() != block_count * 4 + run_count * 2
+        {
+            return None;
+        }
+
+        let (blocks, runs) = rest.split_at(block_count * 4);
+
+        let table = Self {
+            size,
+            blocks: blocks
+                .chunks_exact(4)
+                .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+                .collect(),
+            runs: runs.chunks_exact(2).map(|x| [x[0], x[1]]).collect(),
+        };
+
+        for (k, &start) in table.blocks.iter().enumerate() {
+            let end =
+                table.blocks.get(k + 1).map_or(run_count, |&x| x as usize);
+
+            let positions: usize = table
+                .runs
+                .get(start as usize..end)?
+                .iter()
+                .map(|&[_, len]| len as usize + 1)
+                .sum();
+
+            if positions != BLOCK.min(size * 2 - k * BLOCK) {
+                return None;
+            }
+        }
+
+        Some(table)
+    }
+}
+
+fn file_name((whites, blacks): Material) -> String {
+    format!("w{whites}b{blacks}.tb")
+}
+
+// Solved endgames, by material
+#[derive(Default)]
+pub struct Tablebase {
+    tables: HashMap<Material, PackedTable, RandomState>,
+    // Every material with at most this many pieces besides the king has a
+    // table
+    pub max_pieces: usize,
+}
+
+impl Tablebase {
+    pub fn probe(&self, board: BoardState, turn: Faction) -> Option<TbValue> {
+        if board.0[1].count_ones() as usize > self.max_pieces {
+            return None;
+        }
+
+        board.king_coord()?;
+
+        let table = self.tables.get(&material(board))?;
+
+        Some(TbValue::decode(table.get(canonical_index(board), turn)))
+    }
+
+    // Value of the position after `board` moved into `child`, which must be
+    // in a table already solved, or in `current` for the table being solved.
+    // Unsolved positions in `current` are None.
+    fn child_value(
+        &self,
+        child: BoardState,
+        turn: Faction,
+        current: (Material, &[u8]),
+    ) -> Option<TbValue> {
+        let (current_material, values) = current;
+
+        if material(child) == current_material {
+            let i = canonical_index(child) * 2 + turn as usize;
+
+            (values[i] != 0).then(|| TbValue::decode(values[i]))
+        } else {
+            Some(self.probe(child, turn).expect("Smaller table missing"))
+        }
+    }
+
+    // What the moves of `board` show before anything in its own table is
+    // solved: a win if a move wins or captures into a lost position, a loss
+    // if every move captures into a won position
+    fn scan_moves(&self, board: BoardState, turn: Faction) -> Option<TbValue> {
+        let mut best_win = None::<u32>;
+        let mut longest_loss = Some(0);
+
+        for [from, to] in board.all_moves(turn) {
+            let mut child = board;
+
+            if child.do_move(from, to) {
+                return Some(TbValue::Win(1));
+            }
+
+            // Positions without a capture are all unsolved still
+            if material(child) == material(board) {
+                longest_loss = None;
+                continue;
+            }
+
+            let value = self
+                .probe(child, turn.other_faction())
+                .expect("Smaller table missing");
+
+            match value {
+                TbValue::Loss(n) => {
+                    best_win = Some(best_win.map_or(n + 1, |x| x.min(n + 1)))
+                }
+                TbValue::Win(n) => {
+                    longest_loss = longest_loss.map(|x: u32| x.max(n + 1))
+                }
+                TbValue::Draw => longest_loss = None,
+            }
+        }
+
+        match (best_win, longest_loss) {
+            (Some(n), _) => Some(TbValue::Win(n)),
+            (None, Some(n)) => Some(TbValue::Loss(n)),
+            (None, None) => None,
+        }
+    }
+
+    // The length of the loss if every move of `board` leads to a won
+    // position
+    fn forced_loss(
+        &self,
+        board: BoardState,
+        turn: Faction,
+        current: (Material, &[u8]),
+    ) -> Option<u32> {
+        board.all_moves(turn).try_fold(0, |longest, [from, to]| {
+            let mut child = board;
+
+            if child.do_move(from, to) {
+                return None;
+            }
+
+            match self.child_value(child, turn.other_faction(), current)? {
+                TbValue::Win(n) => Some(longest.max(n + 1)),
+                _ => None,
+            }
+        })
+    }
+
+    // Indices of the positions in the same table that the moves of `board`
+    // lead to, each listed once however many moves lead to it
+    fn quiet_children(board: BoardState, turn: Faction) -> Vec<usize> {
+        let mut children: Vec<_> = board
+            .all_moves(turn)
+            .filter_map(|[from, to]| {
+                let mut child = board;
+
+                (!child.do_move(from, to) && material(child) == material(board))
+                    .then(|| canonical_index(child))
+            })
+            .collect();
+
+        children.sort_unstable();
+        children.dedup();
+
+        children
+    }
+
+    // Positions that `board` can be reached from by a move of `turn` that
+    // does not capture or win
+    fn predecessors(board: BoardState, turn: Faction) -> Vec<BoardState> {
+        let mut parents = Vec::new();
+
+        let pieces = board.select_faction(turn);
+        let occupied = board.0[0] | board.0[1];
+
+        for i in pieces.trues_iter() {
+            let [y, x] = [(i / W) as isize, (i % W) as isize];
+
+            for [dy, dx] in [[1, 0], [0, 1], [-1, 0], [0, -1]] {
+                for step in 1.. {
+                    let [fy, fx] = [y + dy * step, x + dx * step];
+
+                    if !(0..W as isize).contains(&fy)
+                        || !(0..W as isize).contains(&fx)
+                        || occupied[fy as usize * W + fx as usize]
+                    {
+                        break;
+                    }
+
+                    let from = [fy as u16, fx as u16];
+                    let to = [y as u16, x as u16];
+
+                    let mut parent = board;
+                    parent.set_2d(from, board.get(i));
+                    parent.set(i, Piece::Empty);
+
+                    if !parent.moves_from(from)[i] || impossible(parent) {
+                        continue;
+                    }
+
+                    let mut moved = parent;
+
+                    if !moved.do_move(from, to) && moved == board {
+                        parents.push(parent);
+                    }
+                }
+            }
+        }
+
+        parents
+    }
+
+    // Solves the positions with `material`, given the tables for every
+    // smaller material it can capture down to
+    fn solve(&self, material: Material) -> Result<Vec<u8>, String> {
+        let size = table_size(material);
+        let mut values = vec![0u8; size * 2];
+
+        // Positions whose value is known but whose parents have not been
+        // updated yet, by distance. The value is written to `values` when a
+        // position is queued, and a win can still be replaced by a shorter
+        // one while it waits. The replaced entry is then skipped.
+        let mut pending: Vec<Vec<usize>> = Vec::new();
+
+        let queue = |pending: &mut Vec<Vec<_>>,
+                     values: &mut [u8],
+                     i: usize,
+                     value: TbValue| {
+            let n = value.plies() as usize;
+
+            if pending.len() <= n {
+                pending.resize(n + 1, Vec::new());
+            }
+
+            values[i] = value.encode()?;
+            pending[n].push(i);
+
+            Ok::<_, String>(())
+        };
+
+        // For each unsolved position, how many of its quiet children are not
+        // known to be won yet. When none are left, every move loses. A piece
+        // has at most 20 moves, so this fits in a byte for up to 12 pieces.
+        let mut unsolved_children = vec![0u8; size * 2];
+
+        for index in 0..size {
+            let Some(board) = board_at(index, material) else {
+                continue;
+            };
+
+            for turn in [Faction::Black, Faction::White] {
+                let i = index * 2 + turn as usize;
+
+                match self.scan_moves(board, turn) {
+                    Some(value) => queue(&mut pending, &mut values, i, value)?,
+                    None => {
+                        let children = Self::quiet_children(board, turn);
+
+                        unsolved_children[i] = u8::try_from(children.len())
+                            .expect("Too many moves to count in a byte")
+                    }
+                }
+            }
+        }
+
+        let mut plies = 0;
+
+        while plies < pending.len() {
+            for i in mem::take(&mut pending[plies]) {
+                let value = TbValue::decode(values[i]);
+
+                if value.plies() as usize != plies {
+                    continue;
+                }
+
+                let board = board_at(i / 2, material).unwrap();
+                let turn = [Faction::Black, Faction::White][i % 2];
+                let mover = turn.other_faction();
+
+                // A parent can reach this position by more than one move,
+                // but it was counted once in `unsolved_children`
+                let mut parents: Vec<_> = Self::predecessors(board, mover)
+                    .into_iter()
+                    .map(|parent| canonical_index(parent) * 2 + mover as usize)
+                    .collect();
+
+                parents.sort_unstable();
+                parents.dedup();
+
+                for parent_i in parents {
+                    let parent_value = TbValue::decode(values[parent_i]);
+
+                    match value {
+                        TbValue::Loss(n) => {
+                            let shorter = match parent_value {
+                                TbValue::Draw => true,
+                                TbValue::Win(m) => m > n + 1,
+                                TbValue::Loss(_) => false,
+                            };
+
+                            if shorter {
+                                queue(
+                                    &mut pending,
+                                    &mut values,
+                                    parent_i,
+                                    TbValue::Win(n + 1),
+                                )?;
+                            }
+                        }
+                        _ => {
+                            if parent_value != TbValue::Draw {
+                                continue;
+                            }
+
+                            unsolved_children[parent_i] -= 1;
+
+                            if unsolved_children[parent_i] > 0 {
+                                continue;
+                            }
+
+                            let parent =
+                                board_at(parent_i / 2, material).unwrap();
+
+                            if let Some(n) = self.forced_loss(
+                                parent,
+                                mover,
+                                (material, &values),
+                            ) {
+                                queue(
+                                    &mut pending,
+                                    &mut values,
+                                    parent_i,
+                                    TbValue::Loss(n),
+                                )?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            plies += 1;
+        }
+
+        Ok(values)
+    }
+
+    // Solves every material with up to `max_pieces` pieces besides the king,
+    // smallest first, writing a file per material to `dir`
+    pub fn generate(dir: &str, max_pieces: usize) -> Result<Self, String> {
+        if max_pieces > MAX_GENERATED_PIECES {
+            return Err(format!(
+                "Can't generate tables with {max_pieces} pieces besides the \
+                 king, at most {MAX_GENERATED_PIECES} are supported"
+            ));
+        }
+
+        fs::create_dir_all(dir).map_err(|e| format!("{dir}: {e}"))?;
+
+        let mut tablebase = Self::default();
+
+        for pieces in 0..=max_pieces {
+            for whites in 0..=pieces {
+                let material = (whites, pieces - whites);
+
+                let t = Instant::now();
+                let values = tablebase
+                    .solve(material)
+                    .map_err(|e| format!("{}: {e}", file_name(material)))?;
+
+                let count = |f: fn(TbValue) -> bool| {
+                    values.iter().filter(|&&x| f(TbValue::decode(x))).count()
+                };
+
+                println!(
+                    "{}: {} positions, {} wins, {} losses, took {:.2?}",
+                    file_name(material),
+                    values.len(),
+                    count(|x| matches!(x, TbValue::Win(_))),
+                    count(|x| matches!(x, TbValue::Loss(_))),
+                    t.elapsed(),
+                );
+
+                let table = PackedTable::pack(&values);
+                let bytes = table.to_bytes();
+
+                println!(
+                    "{}: packed into {} bytes",
+                    file_name(material),
+                    bytes.len()
+                );
+
+                let path = Path::new(dir).join(file_name(material));
+
+                fs::write(&path, bytes)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+
+                tablebase.tables.insert(material, table);
+            }
+
+            tablebase.max_pieces = pieces;
+        }
+
+        Ok(tablebase)
+    }
+
+    // Loads the table files in `dir`, up to the first piece count with a
+    // table missing
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let mut tablebase = Self::default();
+
+        for pieces in 0.. {
+            for whites in 0..=pieces {
+                let material = (whites, pieces - whites);
+                let path = Path::new(dir).join(file_name(material));
+
+                let Ok(bytes) = fs::read(&path) else {
+                    return match pieces {
+                        0 => Err(format!("No tables in {dir}")),
+                        _ => Ok(tablebase),
+                    };
+                };
+
+                let table =
+                    PackedTable::from_bytes(&bytes, table_size(material))
+                        .ok_or_else(|| {
+                            format!("Invalid table {}", path.display())
+                        })?;
+
+                tablebase.tables.insert(material, table);
+            }
+
+            tablebase.max_pieces = pieces;
+        }
+
+        unreachable!()
+    }
+
+    // Makes this the tablebase probed by every search
+    pub fn set_startup(self) {
+        if STARTUP_TABLEBASE.set(self).is_err() {
+            panic!("Tablebase already set");
+        }
+    }
+
+    pub fn startup() -> Option<&'static Self> {
+        STARTUP_TABLEBASE.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn combination_round_trip() {
+        let mut excluded = BitArray::new();
+        excluded.set(0, true);
+        excluded.set(60, true);
+
+        for k in 0..=2 {
+            for i in 0..binomial(W * W - 2, k) {
+                let squares = combination(i, k, excluded);
+
+                assert_eq!(squares.count_ones() as usize, k);
+                assert_eq!((squares & excluded).count_ones(), 0);
+                assert_eq!(combination_index(squares, excluded), i);
+            }
+        }
+    }
+
+    #[test]
+    fn solve_small_tables() {
+        let board = |pieces: &[([u16; 2], Piece)]| {
+            let mut board = BoardState::new();
+
+            for &(square, piece) in pieces {
+                board.set_2d(square, piece);
+            }
+
+            board
+        };
+
+        let value = |values: &[u8], board, turn: Faction| {
+            TbValue::decode(values[canonical_index(board) * 2 + turn as usize])
+        };
+
+        let mut tablebase = Tablebase::default();
+
+        let lone_king = tablebase.solve((0, 0)).unwrap();
+
+        // Black without pieces has no moves, and the king steps into the
+        // corner
+        let king = board(&[([0, 1], Piece::King)]);
+        assert_eq!(value(&lone_king, king, Faction::Black), TbValue::Loss(0));
+        assert_eq!(value(&lone_king, king, Faction::White), TbValue::Win(1));
+
+        tablebase
+            .tables
+            .insert((0, 0), PackedTable::pack(&lone_king));
+
+        let one_black = tablebase.solve((0, 1)).unwrap();
+
+        // A single black piece can only block one of the two corners the
+        // king threatens
+        let fork = board(&[([0, 2], Piece::King), ([7, 7], Piece::Black)]);
+        assert_eq!(value(&one_black, fork, Faction::White), TbValue::Win(1));
+        assert_eq!(value(&one_black, fork, Faction::Black), TbValue::Loss(2));
+    }
+
+    #[test]
+    fn generate_rejects_large_tables() {
+        let result = Tablebase::generate("unused", MAX_GENERATED_PIECES + 1);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn value_encoding() {
+        for value in [TbValue::Draw, TbValue::Win(127), TbValue::Loss(0)] {
+            assert_eq!(TbValue::decode(value.encode().unwrap()), value);
+        }
+
+        assert!(TbValue::Win(128).encode().is_err());
+        assert!(TbValue::Loss(128).encode().is_err());
+    }
+
+    #[test]
+    fn packed_table_round_trip() {
+        // Long runs, short runs and a last block that isn't full
+        let size = 2 * BLOCK + 300;
+        let value = |index: usize, turn: usize| match index {
+            _ if index < 700 => 1 + turn as u8,
+            _ if index < 2000 => (index % 3) as u8,
+            _ => 130,
+        };
+
+        let values: Vec<_> =
+            (0..size * 2).map(|i| value(i / 2, i % 2)).collect();
+
+        let bytes = PackedTable::pack(&values).to_bytes();
+        let table = PackedTable::from_bytes(&bytes, size).unwrap();
+
+        for index in 0..size {
+            for turn in [Faction::Black, Faction::White] {
+                assert_eq!(table.get(index, turn), value(index, turn as usize));
+            }
+        }
+
+        assert!(
+            PackedTable::from_bytes(&bytes[..bytes.len() - 2], size).is_none()
+        );
+        assert!(PackedTable::from_bytes(&bytes, size + 1).is_none());
+    }
+
+    #[test]
+    fn board_index_round_trip() {
+        for material in [(1, 0), (0, 1)] {
+            for i in 0..table_size(material) {
+                let Some(board) = board_at(i, material) else {
+                    continue;
+                };
+
+                assert_eq!(self::material(board), material);
+                assert_eq!(index(board), i);
+
+                for s in 0..SYMMETRIES {
+                    assert_eq!(canonical_index(board.transformed(s)), i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_position_has_an_index() {
+        for (piece, material) in
+            [(Piece::White, (1, 0)), (Piece::Black, (0, 1))]
+        {
+            let mut indices = HashSet::new();
+
+            for king in 0..W * W {
+                for square in (0..W * W).filter(|&x| x != king) {
+                    let mut board = BoardState::new();
+                    board.set(king, Piece::King);
+                    board.set(square, piece);
+
+                    if impossible(board) {
+                        continue;
+                    }
+
+                    let i = canonical_index(board);
+
+                    assert!(board_at(i, material).is_some());
+                    indices.insert(i);
+                }
+            }
+
+            let positions = (0..table_size(material))
+                .filter(|&i| board_at(i, material).is_some())
+                .count();
+
+            assert_eq!(indices.len(), positions);
+        }
+    }
+}
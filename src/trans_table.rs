@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ahash::RandomState;
+
+use crate::{
+    board::{
+        BoardState, Faction, W, inverse_symmetry, to_linind, transform_square,
+    },
+    bot::{Move, Score},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TtEntry {
+    pub depth: u32,
+    pub score: Score,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+}
+
+const VALID_BIT: u64 = 1 << 63;
+const MOVE_BIT: u64 = 1 << 56;
+
+// The search an entry was stored in, counted modulo GENERATIONS
+const GENERATION_SHIFT: u32 = 57;
+const GENERATIONS: u64 = 64;
+
+fn entry_generation(data: u64) -> u64 {
+    (data >> GENERATION_SHIFT) % GENERATIONS
+}
+
+impl TtEntry {
+    // Layout: score in bits 0-31, depth in 32-39, bound in 40-41, move
+    // squares in 42-48 and 49-55, a flag for whether there is a move, then
+    // the generation in 57-62.
+    fn pack(self, generation: u64) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+
+        let best_move = match self.best_move {
+            Some([from, to]) => {
+                let from = to_linind(from).unwrap() as u64;
+                let to = to_linind(to).unwrap() as u64;
+
+                MOVE_BIT | from << 42 | to << 49
+            }
+            None => 0,
+        };
+
+        VALID_BIT
+            | generation << GENERATION_SHIFT
+            | self.score as u32 as u64
+            | (self.depth.min(255) as u64) << 32
+            | bound << 40
+            | best_move
+    }
+
+    fn unpack(data: u64) -> Self {
+        let square = |i: u64| {
+            let i = (i & 0x7f) as usize;
+            [(i / W) as u16, (i % W) as u16]
+        };
+
+        Self {
+            depth: (data >> 32) as u8 as u32,
+            score: data as u32 as i32,
+            bound: match (data >> 40) & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best_move: (data & MOVE_BIT != 0)
+                .then(|| [square(data >> 42), square(data >> 49)]),
+        }
+    }
+
+    fn transformed(self, symmetry: usize) -> Self {
+        Self {
+            best_move: self
+                .best_move
+                .map(|mv| mv.map(|square| transform_square(square, symmetry))),
+            ..self
+        }
+    }
+}
+
+// Fixed size transposition table that can be shared between search threads
+// without locking. Each slot stores the key xor-ed with the data, so a slot
+// torn by two threads writing at once fails the key check instead of giving
+// back a mix of two entries.
+//
+// A slot keeps a deeper entry from the current search over a shallower one,
+// so helper threads searching shallow lines don't push out the main thread's
+// deep results. Entries from earlier searches are always replaced.
+pub struct TransTable {
+    slots: Box<[[AtomicU64; 2]]>,
+    generation: AtomicU64,
+    hasher: RandomState,
+    // Whether positions are stored by their canonical form, so that turned
+    // or mirrored copies of a position share an entry
+    symmetric: bool,
+}
+
+impl TransTable {
+    pub fn new(size_mb: usize, symmetric: bool) -> Self {
+        let len = (size_mb << 20) / size_of::<[AtomicU64; 2]>();
+
+        Self {
+            slots: (0..len.max(1))
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+            generation: AtomicU64::new(0),
+            hasher: RandomState::with_seeds(
+                0x243f6a8885a308d3,
+                0x13198a2e03707344,
+                0xa4093822299f31d0,
+                0x082efa98ec4e6c89,
+            ),
+            symmetric,
+        }
+    }
+
+    // Starts a new search, after which the entries of earlier searches are
+    // the first to go
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn hash(&self, board: BoardState, turn: Faction) -> u64 {
+        self.hasher.hash_one((board, turn))
+    }
+
+    fn slot(&self, hash: u64) -> &[AtomicU64; 2] {
+        &self.slots[(hash % self.slots.len() as u64) as usize]
+    }
+
+    // The position to store `board` under, and the symmetry that turns
+    // `board` into it
+    fn key(&self, board: BoardState) -> (BoardState, usize) {
+        if self.symmetric {
+            board.canonical()
+        } else {
+            (board, 0)
+        }
+    }
+
+    pub fn get(&self, board: BoardState, turn: Faction) -> Option<TtEntry> {
+        let (board, symmetry) = self.key(board);
+        let hash = self.hash(board, turn);
+        let [key, data] = self.slot(hash);
+
+        let data = data.load(Ordering::Relaxed);
+        let key = key.load(Ordering::Relaxed);
+
+        (data & VALID_BIT != 0 && key ^ data == hash)
+            .then(|| TtEntry::unpack(data))
+            .map(|entry| entry.transformed(inverse_symmetry(symmetry)))
+    }
+
+    pub fn insert(&self, board: BoardState, turn: Faction, entry: TtEntry) {
+        let (board, symmetry) = self.key(board);
+        let hash = self.hash(board, turn);
+        let [key, data] = self.slot(hash);
+
+        let generation = self.generation.load(Ordering::Relaxed) % GENERATIONS;
+        let old = data.load(Ordering::Relaxed);
+
+        if old & VALID_BIT != 0
+            && entry_generation(old) == generation
+            && TtEntry::unpack(old).depth > entry.depth
+            && entry.bound != Bound::Exact
+        {
+            return;
+        }
+
+        let packed = entry.transformed(symmetry).pack(generation);
+
+        key.store(hash ^ packed, Ordering::Relaxed);
+        data.store(packed, Ordering::Relaxed);
+    }
+}
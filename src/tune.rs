@@ -0,0 +1,189 @@
+use std::fs;
+
+use crate::{
+    board::Faction,
+    bot::{Score, Searcher},
+    eval::{EvalTerms, EvalWeights},
+    selfplay::{GameRecord, Outcome},
+};
+
+// Positions this early are mostly the random openings of self-play games
+const SKIP_PLIES: usize = 8;
+
+// The value of a black piece is left as it is, so scores keep their scale
+const FIXED_WEIGHT: usize = 1;
+
+// A quiet position from a game, with its evaluation terms and the result of
+// the game for white: 1 for a win, 0.5 for a draw and 0 for a loss
+struct Sample {
+    terms: [Score; 9],
+    result: f64,
+}
+
+fn load_samples(path: &str) -> Vec<Sample> {
+    let corpus = fs::read_to_string(path).unwrap();
+
+    let mut searcher = Searcher::default();
+    let mut samples = Vec::new();
+
+    for line in corpus.lines().filter(|x| !x.trim().is_empty()) {
+        let record = GameRecord::parse(line).expect("Invalid game record");
+
+        let result = match record.outcome {
+            Outcome::Win(Faction::White) => 1.0,
+            Outcome::Win(Faction::Black) => 0.0,
+            Outcome::Draw => 0.5,
+            Outcome::Unfinished => continue,
+        };
+
+        for (board, turn) in record.positions().skip(SKIP_PLIES) {
+            if searcher.is_quiet(board, turn) {
+                samples.push(Sample {
+                    terms: EvalTerms::of(board).values(),
+                    result,
+                });
+            }
+        }
+    }
+
+    samples
+}
+
+// How evaluations are mapped to expected results for white: the sigmoid
+// scale, and a bias in log10 odds added to every prediction. The search only
+// compares scores, so the bias is not part of the weights. Without it, the
+// constant part of the piece counts and the advantage of whichever side wins
+// more games would be fitted by bending the weights.
+#[derive(Clone, Copy)]
+struct Scale {
+    k: f64,
+    bias: f64,
+}
+
+impl Scale {
+    // Expected result for white of a position with white relative score
+    // `score`
+    fn win_probability(self, score: f64) -> f64 {
+        1.0 / (1.0 + 10f64.powf(-(self.k * score / 400.0 + self.bias)))
+    }
+}
+
+// Mean squared difference between game results and the results predicted
+// from the static evaluation
+fn error(samples: &[Sample], weights: &[Score; 9], scale: Scale) -> f64 {
+    samples
+        .iter()
+        .map(|sample| {
+            let score: Score =
+                sample.terms.iter().zip(weights).map(|(t, w)| t * w).sum();
+
+            (sample.result - scale.win_probability(score as f64)).powi(2)
+        })
+        .sum::<f64>()
+        / samples.len() as f64
+}
+
+// Point in `lo..hi` where `f` is lowest, for `f` with a single minimum there
+fn minimise(mut lo: f64, mut hi: f64, f: impl Fn(f64) -> f64) -> f64 {
+    for _ in 0..60 {
+        let a = lo + (hi - lo) / 3.0;
+        let b = hi - (hi - lo) / 3.0;
+
+        if f(a) < f(b) {
+            hi = b;
+        } else {
+            lo = a;
+        }
+    }
+
+    (lo + hi) / 2.0
+}
+
+// Scale that best fits the current weights, starting from `scale`. K and the
+// bias are fitted in turns.
+fn fit_scale(
+    samples: &[Sample],
+    weights: &[Score; 9],
+    mut scale: Scale,
+) -> Scale {
+    for _ in 0..10 {
+        scale.bias = minimise(-4.0, 4.0, |bias| {
+            error(samples, weights, Scale { bias, ..scale })
+        });
+
+        scale.k = minimise(0.0, 4.0, |k| {
+            error(samples, weights, Scale { k, ..scale })
+        });
+    }
+
+    scale
+}
+
+// Texel tuning: positions from the game records in `corpus_path` are labelled
+// with the results of their games, and every weight is moved up or down while
+// that lowers the error of predicting the results from the evaluation, with
+// the scale refitted after every pass. The tuned weights are written to
+// `out_path` in the `--eval-weights` format.
+pub fn run(corpus_path: &str, out_path: &str, max_passes: usize) {
+    let samples = load_samples(corpus_path);
+
+    println!("{} quiet positions", samples.len());
+
+    let mut weights = EvalWeights::startup();
+    let mut values = weights.values_mut().map(|x| *x);
+
+    let mut scale = fit_scale(&samples, &values, Scale { k: 0.0, bias: 0.0 });
+    let mut best_error = error(&samples, &values, scale);
+
+    println!(
+        "K = {:.3}, bias = {:.3}, error = {best_error:.6}",
+        scale.k, scale.bias
+    );
+
+    let mut step = 16;
+
+    for pass in 1..=max_passes {
+        let mut improved = false;
+
+        for i in (0..values.len()).filter(|&i| i != FIXED_WEIGHT) {
+            for delta in [step, -step] {
+                let mut candidate = values;
+                candidate[i] += delta;
+
+                let e = error(&samples, &candidate, scale);
+
+                if e < best_error {
+                    best_error = e;
+                    values = candidate;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+
+        scale = fit_scale(&samples, &values, scale);
+        best_error = error(&samples, &values, scale);
+
+        println!(
+            "Pass {pass}: step {step}, K = {:.3}, bias = {:.3}, \
+             error = {best_error:.6}",
+            scale.k, scale.bias
+        );
+
+        if !improved {
+            if step == 1 {
+                break;
+            }
+
+            step /= 2;
+        }
+    }
+
+    for (weight, value) in weights.values_mut().into_iter().zip(values) {
+        *weight = value;
+    }
+
+    print!("{weights}");
+
+    fs::write(out_path, weights.to_string()).unwrap();
+}